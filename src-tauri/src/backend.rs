@@ -0,0 +1,469 @@
+use crate::error::CaptureError;
+use image::{Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::sync::Arc;
+use xcap::{Monitor, Window};
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WindowInfo {
+    pub id: u32,
+    pub pid: u32,
+    pub title: String,
+    pub app_name: String,
+    pub x: i32,
+    pub y: i32,
+    /// Stacking order as reported by the platform; higher is closer to the front.
+    pub z: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Monitor the window is mostly on.
+    pub monitor_id: Option<u32>,
+    pub is_focused: bool,
+    pub is_minimized: bool,
+    pub is_maximized: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MonitorInfo {
+    pub id: u32,
+    /// Identifier derived from the monitor name that survives reboots and
+    /// reconnects, unlike the platform `id`.
+    pub stable_id: String,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Physical pixels per logical pixel.
+    pub scale_factor: f32,
+    /// Rotation in degrees.
+    pub rotation: f32,
+    /// Refresh rate in Hz.
+    pub frequency: f32,
+    pub is_primary: bool,
+    pub is_builtin: bool,
+}
+
+/// Fills in `stable_id` from each monitor's name. Monitors sharing a name
+/// (e.g. two identical models) are told apart by their position, left to
+/// right then top to bottom.
+pub fn assign_stable_ids(monitors: &mut [MonitorInfo]) {
+    let mut order: Vec<usize> = (0..monitors.len()).collect();
+    order.sort_by_key(|&i| (monitors[i].x, monitors[i].y));
+
+    let mut seen: HashMap<String, u32> = HashMap::new();
+    for i in order {
+        let base: String = monitors[i]
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        let base = if base.trim_matches('-').is_empty() {
+            "monitor".to_string()
+        } else {
+            base
+        };
+        let count = seen.entry(base.clone()).or_insert(0);
+        *count += 1;
+        monitors[i].stable_id = if *count == 1 {
+            base
+        } else {
+            format!("{}#{}", base, count)
+        };
+    }
+}
+
+/// Source of monitors, windows and their pixels.
+///
+/// Commands only talk to this trait, so the whole command layer can run
+/// against `SyntheticBackend` where no display is available.
+pub trait CaptureBackend: Send + Sync {
+    fn monitors(&self) -> Result<Vec<MonitorInfo>, CaptureError>;
+    fn windows(&self) -> Result<Vec<WindowInfo>, CaptureError>;
+    /// Returns the ID of the monitor containing the given desktop point.
+    fn monitor_at_point(&self, x: i32, y: i32) -> Result<Option<u32>, CaptureError>;
+    fn capture_monitor(&self, id: u32) -> Result<RgbaImage, CaptureError>;
+    fn capture_window(&self, id: u32) -> Result<RgbaImage, CaptureError>;
+
+    /// Captures several windows, one result per ID in the same order.
+    fn capture_windows(&self, ids: &[u32]) -> Vec<Result<RgbaImage, CaptureError>> {
+        ids.iter().map(|&id| self.capture_window(id)).collect()
+    }
+}
+
+/// Resolves the requested monitor ID, falling back to the primary monitor
+/// (or the first one) when no ID is given.
+pub fn resolve_monitor(
+    backend: &dyn CaptureBackend,
+    monitor_id: Option<u32>,
+) -> Result<MonitorInfo, CaptureError> {
+    let monitors = backend.monitors()?;
+    if let Some(id) = monitor_id {
+        monitors
+            .into_iter()
+            .find(|m| m.id == id)
+            .ok_or(CaptureError::MonitorNotFound { id: Some(id) })
+    } else {
+        let primary = monitors.iter().position(|m| m.is_primary).unwrap_or(0);
+        monitors
+            .into_iter()
+            .nth(primary)
+            .ok_or(CaptureError::MonitorNotFound { id: None })
+    }
+}
+
+pub fn find_window(backend: &dyn CaptureBackend, id: u32) -> Result<WindowInfo, CaptureError> {
+    backend
+        .windows()?
+        .into_iter()
+        .find(|w| w.id == id)
+        .ok_or(CaptureError::WindowNotFound { id })
+}
+
+/// Windows belonging to this app are never picked by focus or cursor
+/// position, so a hotkey capture grabs what the user was working in.
+fn is_own_window(window: &WindowInfo) -> bool {
+    window.pid == std::process::id()
+}
+
+/// Returns the focused window.
+pub fn active_window(backend: &dyn CaptureBackend) -> Result<WindowInfo, CaptureError> {
+    backend
+        .windows()?
+        .into_iter()
+        .find(|w| w.is_focused && !is_own_window(w))
+        .ok_or_else(|| CaptureError::NoMatchingWindow("No focused window".to_string()))
+}
+
+/// Returns the frontmost visible window containing the given desktop point.
+pub fn window_at_point(
+    backend: &dyn CaptureBackend,
+    x: i32,
+    y: i32,
+) -> Result<WindowInfo, CaptureError> {
+    backend
+        .windows()?
+        .into_iter()
+        .filter(|w| !w.is_minimized && !is_own_window(w))
+        .filter(|w| {
+            x >= w.x
+                && (x as i64) < w.x as i64 + w.width as i64
+                && y >= w.y
+                && (y as i64) < w.y as i64 + w.height as i64
+        })
+        .max_by_key(|w| w.z)
+        .ok_or_else(|| CaptureError::NoMatchingWindow(format!("No window at ({}, {})", x, y)))
+}
+
+/// Picks the backend for this process. Setting `CAPTURE_BACKEND=synthetic`
+/// swaps in generated test patterns, e.g. for headless CI.
+pub fn from_env() -> Arc<dyn CaptureBackend> {
+    match std::env::var("CAPTURE_BACKEND").as_deref() {
+        Ok("synthetic") => Arc::new(SyntheticBackend::default()),
+        _ => Arc::new(XcapBackend),
+    }
+}
+
+/// Real screen capture through `xcap`.
+pub struct XcapBackend;
+
+impl XcapBackend {
+    fn find_monitor(id: u32) -> Result<Monitor, CaptureError> {
+        Monitor::all()?
+            .into_iter()
+            .find(|m| m.id().unwrap_or(0) == id)
+            .ok_or(CaptureError::MonitorNotFound { id: Some(id) })
+    }
+
+    fn find_window(id: u32) -> Result<Window, CaptureError> {
+        Window::all()?
+            .into_iter()
+            .find(|w| w.id().unwrap_or(0) == id)
+            .ok_or(CaptureError::WindowNotFound { id })
+    }
+}
+
+impl CaptureBackend for XcapBackend {
+    fn monitors(&self) -> Result<Vec<MonitorInfo>, CaptureError> {
+        let monitors = Monitor::all()?;
+        let mut infos: Vec<MonitorInfo> = monitors
+            .into_iter()
+            .map(|m| MonitorInfo {
+                id: m.id().unwrap_or(0),
+                stable_id: String::new(),
+                name: m.name().unwrap_or_default(),
+                x: m.x().unwrap_or(0),
+                y: m.y().unwrap_or(0),
+                width: m.width().unwrap_or(0),
+                height: m.height().unwrap_or(0),
+                scale_factor: m.scale_factor().unwrap_or(1.0),
+                rotation: m.rotation().unwrap_or(0.0),
+                frequency: m.frequency().unwrap_or(0.0),
+                is_primary: m.is_primary().unwrap_or(false),
+                is_builtin: m.is_builtin().unwrap_or(false),
+            })
+            .collect();
+        assign_stable_ids(&mut infos);
+        Ok(infos)
+    }
+
+    fn windows(&self) -> Result<Vec<WindowInfo>, CaptureError> {
+        let windows = Window::all()?;
+        Ok(windows
+            .into_iter()
+            .filter_map(|w| {
+                Some(WindowInfo {
+                    id: w.id().ok()?,
+                    pid: w.pid().unwrap_or(0),
+                    title: w.title().unwrap_or_default(),
+                    app_name: w.app_name().unwrap_or_default(),
+                    x: w.x().unwrap_or(0),
+                    y: w.y().unwrap_or(0),
+                    z: w.z().unwrap_or(0),
+                    width: w.width().unwrap_or(0),
+                    height: w.height().unwrap_or(0),
+                    monitor_id: w.current_monitor().and_then(|m| m.id()).ok(),
+                    is_focused: w.is_focused().unwrap_or(false),
+                    is_minimized: w.is_minimized().unwrap_or(false),
+                    is_maximized: w.is_maximized().unwrap_or(false),
+                })
+            })
+            .collect())
+    }
+
+    fn monitor_at_point(&self, x: i32, y: i32) -> Result<Option<u32>, CaptureError> {
+        let monitors = Monitor::all()?;
+        for m in monitors {
+            let mx = m.x().unwrap_or(0);
+            let my = m.y().unwrap_or(0);
+            let width = m.width().unwrap_or(0) as i32;
+            let height = m.height().unwrap_or(0) as i32;
+
+            if x >= mx && x < mx + width && y >= my && y < my + height {
+                return Ok(m.id().ok());
+            }
+        }
+        Ok(None)
+    }
+
+    fn capture_monitor(&self, id: u32) -> Result<RgbaImage, CaptureError> {
+        Ok(Self::find_monitor(id)?.capture_image()?)
+    }
+
+    fn capture_window(&self, id: u32) -> Result<RgbaImage, CaptureError> {
+        Ok(Self::find_window(id)?.capture_image()?)
+    }
+
+    fn capture_windows(&self, ids: &[u32]) -> Vec<Result<RgbaImage, CaptureError>> {
+        // Enumerate once instead of once per window
+        let windows = match Window::all() {
+            Ok(windows) => windows,
+            Err(e) => {
+                // Classify through `From` so a missing permission is kept
+                let message = e.to_string();
+                let denied = matches!(CaptureError::from(e), CaptureError::PermissionDenied(_));
+                return ids
+                    .iter()
+                    .map(|_| {
+                        Err(if denied {
+                            CaptureError::PermissionDenied(message.clone())
+                        } else {
+                            CaptureError::CaptureFailed(message.clone())
+                        })
+                    })
+                    .collect();
+            }
+        };
+        ids.iter()
+            .map(|&id| {
+                let window = windows
+                    .iter()
+                    .find(|w| w.id().unwrap_or(0) == id)
+                    .ok_or(CaptureError::WindowNotFound { id })?;
+                Ok(window.capture_image()?)
+            })
+            .collect()
+    }
+}
+
+/// Deterministic backend that never touches the display. Captures are
+/// generated test patterns seeded by the monitor or window ID, so the same
+/// request always yields the same pixels.
+#[derive(Debug, Clone)]
+pub struct SyntheticBackend {
+    pub monitors: Vec<MonitorInfo>,
+    pub windows: Vec<WindowInfo>,
+}
+
+impl Default for SyntheticBackend {
+    fn default() -> Self {
+        Self {
+            monitors: vec![
+                MonitorInfo {
+                    id: 1,
+                    stable_id: "synthetic-primary".to_string(),
+                    name: "Synthetic Primary".to_string(),
+                    x: 0,
+                    y: 0,
+                    width: 1920,
+                    height: 1080,
+                    scale_factor: 1.0,
+                    rotation: 0.0,
+                    frequency: 60.0,
+                    is_primary: true,
+                    is_builtin: true,
+                },
+                MonitorInfo {
+                    id: 2,
+                    stable_id: "synthetic-secondary".to_string(),
+                    name: "Synthetic Secondary".to_string(),
+                    x: 1920,
+                    y: 0,
+                    width: 1280,
+                    height: 1024,
+                    scale_factor: 1.0,
+                    rotation: 0.0,
+                    frequency: 75.0,
+                    is_primary: false,
+                    is_builtin: false,
+                },
+            ],
+            windows: vec![
+                WindowInfo {
+                    id: 101,
+                    pid: 4101,
+                    title: "Untitled - Editor".to_string(),
+                    app_name: "Editor".to_string(),
+                    x: 100,
+                    y: 100,
+                    z: 4,
+                    width: 800,
+                    height: 600,
+                    monitor_id: Some(1),
+                    is_focused: true,
+                    is_minimized: false,
+                    is_maximized: false,
+                },
+                WindowInfo {
+                    id: 102,
+                    pid: 4102,
+                    title: "Untitled - Editor".to_string(),
+                    app_name: "Editor".to_string(),
+                    x: 300,
+                    y: 200,
+                    z: 3,
+                    width: 640,
+                    height: 480,
+                    monitor_id: Some(1),
+                    is_focused: false,
+                    is_minimized: false,
+                    is_maximized: false,
+                },
+                WindowInfo {
+                    id: 103,
+                    pid: 5200,
+                    title: "Terminal".to_string(),
+                    app_name: "Terminal".to_string(),
+                    x: 1920,
+                    y: 0,
+                    z: 2,
+                    width: 1280,
+                    height: 1024,
+                    monitor_id: Some(2),
+                    is_focused: false,
+                    is_minimized: false,
+                    is_maximized: true,
+                },
+                WindowInfo {
+                    id: 104,
+                    pid: 6300,
+                    title: "Tooltip".to_string(),
+                    app_name: "Overlay".to_string(),
+                    x: 500,
+                    y: 500,
+                    z: 5,
+                    width: 32,
+                    height: 16,
+                    monitor_id: Some(1),
+                    is_focused: false,
+                    is_minimized: false,
+                    is_maximized: false,
+                },
+                WindowInfo {
+                    id: 105,
+                    pid: 7400,
+                    title: "Minimized Notes".to_string(),
+                    app_name: "Notes".to_string(),
+                    x: 0,
+                    y: 0,
+                    z: 1,
+                    width: 400,
+                    height: 300,
+                    monitor_id: Some(1),
+                    is_focused: false,
+                    is_minimized: true,
+                    is_maximized: false,
+                },
+            ],
+        }
+    }
+}
+
+impl SyntheticBackend {
+    /// Gradient with a checkerboard overlay; `seed` shifts the colors so
+    /// different sources are distinguishable.
+    pub fn test_pattern(width: u32, height: u32, seed: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let r = (x * 255 / width.max(1)) as u8;
+            let g = (y * 255 / height.max(1)) as u8;
+            let b = (seed.wrapping_mul(47) % 256) as u8;
+            if (x / 32 + y / 32) % 2 == 0 {
+                Rgba([r, g, b, 255])
+            } else {
+                Rgba([255 - r, 255 - g, b, 255])
+            }
+        })
+    }
+}
+
+impl CaptureBackend for SyntheticBackend {
+    fn monitors(&self) -> Result<Vec<MonitorInfo>, CaptureError> {
+        Ok(self.monitors.clone())
+    }
+
+    fn windows(&self) -> Result<Vec<WindowInfo>, CaptureError> {
+        Ok(self.windows.clone())
+    }
+
+    fn monitor_at_point(&self, x: i32, y: i32) -> Result<Option<u32>, CaptureError> {
+        Ok(self
+            .monitors
+            .iter()
+            .find(|m| x >= m.x && x < m.x + m.width as i32 && y >= m.y && y < m.y + m.height as i32)
+            .map(|m| m.id))
+    }
+
+    fn capture_monitor(&self, id: u32) -> Result<RgbaImage, CaptureError> {
+        let monitor = self
+            .monitors
+            .iter()
+            .find(|m| m.id == id)
+            .ok_or(CaptureError::MonitorNotFound { id: Some(id) })?;
+        Ok(Self::test_pattern(monitor.width, monitor.height, id))
+    }
+
+    fn capture_window(&self, id: u32) -> Result<RgbaImage, CaptureError> {
+        let window = self
+            .windows
+            .iter()
+            .find(|w| w.id == id)
+            .ok_or(CaptureError::WindowNotFound { id })?;
+        Ok(Self::test_pattern(window.width, window.height, id))
+    }
+}
@@ -0,0 +1,157 @@
+use crate::backend::WindowInfo;
+use serde::ser::SerializeMap;
+use std::path::{Path, PathBuf};
+
+/// Error returned by every command.
+///
+/// Serializes as an object with a stable machine-readable `code`, a
+/// human-readable `message` and any variant details, e.g.
+/// `{ "code": "monitor_not_found", "message": "Monitor 3 not found", "id": 3 }`.
+#[derive(Debug, thiserror::Error)]
+pub enum CaptureError {
+    #[error("{}", match id {
+        Some(id) => format!("Monitor {} not found", id),
+        None => "No monitor found".to_string(),
+    })]
+    MonitorNotFound { id: Option<u32> },
+    #[error("Window {id} not found")]
+    WindowNotFound { id: u32 },
+    #[error("{0}")]
+    NoMatchingWindow(String),
+    #[error("{} windows match: {}", candidates.len(), candidates.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", "))]
+    AmbiguousWindow { candidates: Vec<WindowCandidate> },
+    #[error("Capture {id} not found")]
+    CaptureNotFound { id: u64 },
+    #[error("Recording file {id} is not open")]
+    SessionNotFound { id: u64 },
+    #[error("Stream {id} not found")]
+    StreamNotFound { id: u64 },
+    #[error("Screen capture permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Capture failed: {0}")]
+    CaptureFailed(String),
+    #[error("Encoding failed: {0}")]
+    EncodeFailed(String),
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{} already exists", path.display())]
+    FileExists { path: PathBuf },
+    #[error("Writing to {} is not allowed", path.display())]
+    PathNotAllowed { path: PathBuf },
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    #[error("Clipboard error: {0}")]
+    Clipboard(String),
+}
+
+/// A window listed in an `AmbiguousWindow` error.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WindowCandidate {
+    pub id: u32,
+    pub app_name: String,
+    pub title: String,
+}
+
+impl From<&WindowInfo> for WindowCandidate {
+    fn from(w: &WindowInfo) -> Self {
+        Self {
+            id: w.id,
+            app_name: w.app_name.clone(),
+            title: w.title.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for WindowCandidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} \"{}\" ({})", self.app_name, self.title, self.id)
+    }
+}
+
+impl CaptureError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CaptureError::MonitorNotFound { .. } => "monitor_not_found",
+            CaptureError::WindowNotFound { .. } => "window_not_found",
+            CaptureError::NoMatchingWindow(_) => "no_matching_window",
+            CaptureError::AmbiguousWindow { .. } => "ambiguous_window",
+            CaptureError::CaptureNotFound { .. } => "capture_not_found",
+            CaptureError::SessionNotFound { .. } => "session_not_found",
+            CaptureError::StreamNotFound { .. } => "stream_not_found",
+            CaptureError::PermissionDenied(_) => "permission_denied",
+            CaptureError::CaptureFailed(_) => "capture_failed",
+            CaptureError::EncodeFailed(_) => "encode_failed",
+            CaptureError::Io { .. } => "io",
+            CaptureError::FileExists { .. } => "file_exists",
+            CaptureError::PathNotAllowed { .. } => "path_not_allowed",
+            CaptureError::InvalidInput(_) => "invalid_input",
+            CaptureError::Clipboard(_) => "clipboard",
+        }
+    }
+
+    /// Wraps an I/O error with the path it happened on.
+    pub fn io(path: impl AsRef<Path>, source: std::io::Error) -> Self {
+        CaptureError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        CaptureError::InvalidInput(message.into())
+    }
+}
+
+impl serde::Serialize for CaptureError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", &self.to_string())?;
+        match self {
+            CaptureError::MonitorNotFound { id } => map.serialize_entry("id", id)?,
+            CaptureError::WindowNotFound { id } => map.serialize_entry("id", id)?,
+            CaptureError::CaptureNotFound { id }
+            | CaptureError::SessionNotFound { id }
+            | CaptureError::StreamNotFound { id } => map.serialize_entry("id", id)?,
+            CaptureError::AmbiguousWindow { candidates } => {
+                map.serialize_entry("candidates", candidates)?
+            }
+            CaptureError::Io { path, source } => {
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("kind", &source.kind().to_string())?;
+            }
+            CaptureError::FileExists { path } | CaptureError::PathNotAllowed { path } => {
+                map.serialize_entry("path", path)?
+            }
+            _ => {}
+        }
+        map.end()
+    }
+}
+
+impl From<xcap::XCapError> for CaptureError {
+    fn from(e: xcap::XCapError) -> Self {
+        // xcap reports missing screen-recording permission as plain platform
+        // errors, so classify by message
+        let message = e.to_string();
+        let lower = message.to_lowercase();
+        if ["permission", "denied", "not permitted", "unauthorized"]
+            .iter()
+            .any(|needle| lower.contains(needle))
+        {
+            CaptureError::PermissionDenied(message)
+        } else {
+            CaptureError::CaptureFailed(message)
+        }
+    }
+}
+
+impl From<image::ImageError> for CaptureError {
+    fn from(e: image::ImageError) -> Self {
+        CaptureError::EncodeFailed(e.to_string())
+    }
+}
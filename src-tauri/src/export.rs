@@ -0,0 +1,475 @@
+use crate::error::CaptureError;
+use crate::optimize::{self, QuantizeOptions};
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::ico::IcoEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{self, PngEncoder};
+use image::codecs::qoi::QoiEncoder;
+use image::codecs::tiff::TiffEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{imageops, DynamicImage, ExtendedColorType, ImageEncoder, Rgb, RgbImage, RgbaImage};
+use std::io::Cursor;
+use std::path::Path;
+
+/// ICO entries cannot be larger than this in either dimension.
+const ICO_MAX_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Png,
+    Jpeg,
+    /// Lossless WebP; the `image` crate has no lossy WebP encoder.
+    Webp,
+    Bmp,
+    Tiff,
+    Qoi,
+    Ico,
+}
+
+impl ExportFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "webp" => Some(Self::Webp),
+            "bmp" => Some(Self::Bmp),
+            "tif" | "tiff" => Some(Self::Tiff),
+            "qoi" => Some(Self::Qoi),
+            "ico" => Some(Self::Ico),
+            _ => None,
+        }
+    }
+
+    fn supports_alpha(self) -> bool {
+        !matches!(self, Self::Jpeg)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    #[default]
+    Adaptive,
+}
+
+/// Per-format encoding options. Every field has a default, so the webview
+/// only sends what it wants to change.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ExportOptions {
+    /// Target format; inferred from the file extension when omitted.
+    pub format: Option<ExportFormat>,
+    /// JPEG quality, 1-100.
+    pub jpeg_quality: u8,
+    pub png_compression: PngCompression,
+    pub png_filter: PngFilter,
+    /// When set, transparent pixels are blended onto this RGB color. Formats
+    /// without alpha (JPEG) always flatten, onto white if unset.
+    pub background: Option<[u8; 3]>,
+    /// PNG only: lossless oxipng pass at this preset, 0 (fast) to 6 (smallest).
+    pub optimize: Option<u8>,
+    /// PNG only: lossy reduction to an 8-bit palette.
+    pub quantize: Option<QuantizeOptions>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            jpeg_quality: 90,
+            png_compression: PngCompression::Default,
+            png_filter: PngFilter::Adaptive,
+            background: None,
+            optimize: None,
+            quantize: None,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Resolves the target format from the options or the destination path.
+    pub fn format_for(&self, path: &Path) -> Result<ExportFormat, CaptureError> {
+        self.format
+            .or_else(|| ExportFormat::from_path(path))
+            .ok_or_else(|| {
+                CaptureError::invalid(format!("Cannot infer image format from {}", path.display()))
+            })
+    }
+}
+
+fn flatten(image: &RgbaImage, background: [u8; 3]) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let blend =
+            |c: u8, bg: u8| ((c as u32 * a as u32 + bg as u32 * (255 - a as u32)) / 255) as u8;
+        Rgb([
+            blend(r, background[0]),
+            blend(g, background[1]),
+            blend(b, background[2]),
+        ])
+    })
+}
+
+/// Encodes `image` in `format`, applying the relevant options.
+pub fn encode(
+    image: &RgbaImage,
+    format: ExportFormat,
+    options: &ExportOptions,
+) -> Result<Vec<u8>, CaptureError> {
+    let background = match (options.background, format.supports_alpha()) {
+        (Some(color), _) => Some(color),
+        (None, false) => Some([255, 255, 255]),
+        (None, true) => None,
+    };
+
+    let resized;
+    let image = if format == ExportFormat::Ico
+        && (image.width() > ICO_MAX_SIZE || image.height() > ICO_MAX_SIZE)
+    {
+        let scale = ICO_MAX_SIZE as f64 / image.width().max(image.height()) as f64;
+        let width = ((image.width() as f64 * scale).round() as u32).max(1);
+        let height = ((image.height() as f64 * scale).round() as u32).max(1);
+        resized = imageops::resize(image, width, height, imageops::FilterType::Lanczos3);
+        &resized
+    } else {
+        image
+    };
+
+    let flattened = background.map(|color| flatten(image, color));
+    let (data, color_type): (&[u8], ExtendedColorType) = match &flattened {
+        Some(rgb) => (rgb.as_raw(), ExtendedColorType::Rgb8),
+        None => (image.as_raw(), ExtendedColorType::Rgba8),
+    };
+    let (width, height) = image.dimensions();
+
+    let mut buffer = Vec::new();
+    match format {
+        ExportFormat::Png => {
+            let compression = match options.png_compression {
+                PngCompression::Fast => png::CompressionType::Fast,
+                PngCompression::Default => png::CompressionType::Default,
+                PngCompression::Best => png::CompressionType::Best,
+            };
+            let filter = match options.png_filter {
+                PngFilter::None => png::FilterType::NoFilter,
+                PngFilter::Sub => png::FilterType::Sub,
+                PngFilter::Up => png::FilterType::Up,
+                PngFilter::Avg => png::FilterType::Avg,
+                PngFilter::Paeth => png::FilterType::Paeth,
+                PngFilter::Adaptive => png::FilterType::Adaptive,
+            };
+            PngEncoder::new_with_quality(&mut buffer, compression, filter)
+                .write_image(data, width, height, color_type)?;
+        }
+        ExportFormat::Jpeg => {
+            let quality = options.jpeg_quality.clamp(1, 100);
+            JpegEncoder::new_with_quality(&mut buffer, quality)
+                .write_image(data, width, height, color_type)?;
+        }
+        ExportFormat::Webp => {
+            WebPEncoder::new_lossless(&mut buffer).write_image(data, width, height, color_type)?;
+        }
+        ExportFormat::Bmp => {
+            BmpEncoder::new(&mut buffer).write_image(data, width, height, color_type)?;
+        }
+        ExportFormat::Tiff => {
+            TiffEncoder::new(Cursor::new(&mut buffer))
+                .write_image(data, width, height, color_type)?;
+        }
+        ExportFormat::Qoi => {
+            QoiEncoder::new(&mut buffer).write_image(data, width, height, color_type)?;
+        }
+        ExportFormat::Ico => {
+            IcoEncoder::new(&mut buffer).write_image(data, width, height, color_type)?;
+        }
+    }
+    Ok(buffer)
+}
+
+/// Encoded file contents, plus the size of the plain encoding when an
+/// optimization pass changed it.
+pub struct Exported {
+    pub data: Vec<u8>,
+    pub unoptimized_size: Option<usize>,
+}
+
+/// Encodes `image` like `encode`, then applies the PNG quantization and
+/// optimization passes requested in `options`. A dithered palette can
+/// compress worse than the truecolor original, so the quantized file is
+/// only kept when it is the smaller one.
+pub fn export(
+    image: &RgbaImage,
+    format: ExportFormat,
+    options: &ExportOptions,
+) -> Result<Exported, CaptureError> {
+    let plain = encode(image, format, options)?;
+    if format != ExportFormat::Png || (options.optimize.is_none() && options.quantize.is_none()) {
+        return Ok(Exported {
+            data: plain,
+            unoptimized_size: None,
+        });
+    }
+
+    let optimize = |data: Vec<u8>| match options.optimize {
+        Some(level) => optimize::optimize_png(&data, level),
+        None => Ok(data),
+    };
+    let quantized = match &options.quantize {
+        Some(quantize) => {
+            let quantized = match options.background {
+                Some(color) => {
+                    let flattened = DynamicImage::ImageRgb8(flatten(image, color)).to_rgba8();
+                    optimize::quantize_png(&flattened, quantize)?
+                }
+                None => optimize::quantize_png(image, quantize)?,
+            };
+            Some(optimize(quantized)?)
+        }
+        None => None,
+    };
+    let data = match quantized {
+        Some(quantized) if quantized.len() < plain.len() => quantized,
+        _ => optimize(plain.clone())?,
+    };
+    Ok(Exported {
+        data,
+        unoptimized_size: Some(plain.len()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A smooth gradient: small as truecolor, noisy once dithered.
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(256, 128, |x, y| Rgba([x as u8, (y * 2) as u8, 128, 255]))
+    }
+
+    /// A handful of colors in a busy pattern with a transparent corner, like
+    /// text in a UI capture.
+    fn screenshot() -> RgbaImage {
+        const COLORS: [Rgba<u8>; 4] = [
+            Rgba([255, 255, 255, 255]),
+            Rgba([30, 30, 30, 255]),
+            Rgba([0, 120, 215, 255]),
+            Rgba([230, 230, 230, 255]),
+        ];
+        RgbaImage::from_fn(200, 120, |x, y| {
+            if x < 20 && y < 20 {
+                return Rgba([0, 0, 0, 0]);
+            }
+            let mut hash = x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663);
+            hash ^= hash >> 13;
+            hash = hash.wrapping_mul(0x5bd1_e995);
+            COLORS[(hash >> 15) as usize % COLORS.len()]
+        })
+    }
+
+    const FORMATS: [(ExportFormat, image::ImageFormat); 7] = [
+        (ExportFormat::Png, image::ImageFormat::Png),
+        (ExportFormat::Jpeg, image::ImageFormat::Jpeg),
+        (ExportFormat::Webp, image::ImageFormat::WebP),
+        (ExportFormat::Bmp, image::ImageFormat::Bmp),
+        (ExportFormat::Tiff, image::ImageFormat::Tiff),
+        (ExportFormat::Qoi, image::ImageFormat::Qoi),
+        (ExportFormat::Ico, image::ImageFormat::Ico),
+    ];
+
+    fn png_color_type(data: &[u8]) -> ::png::ColorType {
+        let reader = ::png::Decoder::new(data).read_info().unwrap();
+        reader.info().color_type
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let image = screenshot();
+        for (format, image_format) in FORMATS {
+            let data = encode(&image, format, &ExportOptions::default()).unwrap();
+            assert_eq!(image::guess_format(&data).unwrap(), image_format);
+            let decoded = image::load_from_memory_with_format(&data, image_format)
+                .unwrap()
+                .to_rgba8();
+            assert_eq!(decoded.dimensions(), image.dimensions(), "{format:?}");
+            if format == ExportFormat::Jpeg {
+                assert_eq!(decoded.get_pixel(5, 5)[3], 255);
+            } else {
+                assert_eq!(decoded, image, "{format:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn formats_come_from_the_extension_unless_set() {
+        let options = ExportOptions::default();
+        let format = options.format_for(Path::new("shot.JPG")).unwrap();
+        assert_eq!(format, ExportFormat::Jpeg);
+        assert_eq!(
+            ExportFormat::from_path(Path::new("a.tif")),
+            Some(ExportFormat::Tiff)
+        );
+        assert!(options.format_for(Path::new("shot.gif")).is_err());
+        assert!(options.format_for(Path::new("shot")).is_err());
+
+        let options = ExportOptions {
+            format: Some(ExportFormat::Qoi),
+            ..Default::default()
+        };
+        let format = options.format_for(Path::new("shot.png")).unwrap();
+        assert_eq!(format, ExportFormat::Qoi);
+    }
+
+    #[test]
+    fn jpeg_quality_trades_size() {
+        let image = gradient();
+        let size = |jpeg_quality| {
+            let options = ExportOptions {
+                jpeg_quality,
+                ..Default::default()
+            };
+            encode(&image, ExportFormat::Jpeg, &options).unwrap().len()
+        };
+        assert!(size(20) < size(60));
+        assert!(size(60) < size(100));
+        assert_eq!(size(0), size(1));
+    }
+
+    #[test]
+    fn jpeg_flattens_transparency() {
+        let image = RgbaImage::from_pixel(16, 16, Rgba([0, 0, 0, 0]));
+        let decode = |options: &ExportOptions| {
+            let data = encode(&image, ExportFormat::Jpeg, options).unwrap();
+            image::load_from_memory(&data).unwrap().to_rgb8()
+        };
+        let white = decode(&ExportOptions::default());
+        assert!(white.get_pixel(8, 8).0.iter().all(|&c| c > 250));
+
+        let options = ExportOptions {
+            background: Some([255, 0, 0]),
+            ..Default::default()
+        };
+        let [r, g, b] = decode(&options).get_pixel(8, 8).0;
+        assert!(r > 240 && g < 15 && b < 15);
+    }
+
+    #[test]
+    fn large_icons_are_scaled_down() {
+        let image = RgbaImage::from_pixel(1000, 500, Rgba([10, 20, 30, 255]));
+        let data = encode(&image, ExportFormat::Ico, &ExportOptions::default()).unwrap();
+        let decoded = image::load_from_memory(&data).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (256, 128));
+    }
+
+    #[test]
+    fn png_settings_are_lossless() {
+        let image = screenshot();
+        for png_compression in [
+            PngCompression::Fast,
+            PngCompression::Default,
+            PngCompression::Best,
+        ] {
+            for png_filter in [PngFilter::None, PngFilter::Paeth, PngFilter::Adaptive] {
+                let options = ExportOptions {
+                    png_compression,
+                    png_filter,
+                    ..Default::default()
+                };
+                let data = encode(&image, ExportFormat::Png, &options).unwrap();
+                assert_eq!(image::load_from_memory(&data).unwrap().to_rgba8(), image);
+            }
+        }
+    }
+
+    #[test]
+    fn png_passes_never_grow_the_file() {
+        let quantizes = [
+            None,
+            Some(QuantizeOptions::default()),
+            Some(QuantizeOptions {
+                colors: 16,
+                dithering: 0.0,
+                speed: 30,
+            }),
+        ];
+        for image in [gradient(), screenshot()] {
+            for quantize in &quantizes {
+                for optimize in [None, Some(0), Some(2)] {
+                    for background in [None, Some([255, 0, 255])] {
+                        let options = ExportOptions {
+                            quantize: quantize.clone(),
+                            optimize,
+                            background,
+                            ..Default::default()
+                        };
+                        let exported = export(&image, ExportFormat::Png, &options).unwrap();
+                        let plain = encode(&image, ExportFormat::Png, &options).unwrap();
+                        let passes = quantize.is_some() || optimize.is_some();
+                        assert_eq!(exported.unoptimized_size, passes.then_some(plain.len()));
+                        assert!(exported.data.len() <= plain.len(), "{options:?}");
+                        let decoded = image::load_from_memory(&exported.data).unwrap();
+                        assert_eq!(decoded.width(), image.width());
+                        assert_eq!(decoded.height(), image.height());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dithered_gradient_falls_back_to_truecolor() {
+        let options = ExportOptions {
+            quantize: Some(QuantizeOptions::default()),
+            optimize: Some(2),
+            ..Default::default()
+        };
+        let exported = export(&gradient(), ExportFormat::Png, &options).unwrap();
+        assert_ne!(png_color_type(&exported.data), ::png::ColorType::Indexed);
+        let decoded = image::load_from_memory(&exported.data).unwrap().to_rgba8();
+        assert_eq!(decoded, gradient());
+    }
+
+    #[test]
+    fn quantized_screenshot_stays_indexed() {
+        let options = ExportOptions {
+            quantize: Some(QuantizeOptions {
+                colors: 16,
+                dithering: 0.0,
+                speed: 1,
+            }),
+            ..Default::default()
+        };
+        let exported = export(&screenshot(), ExportFormat::Png, &options).unwrap();
+        assert_eq!(png_color_type(&exported.data), ::png::ColorType::Indexed);
+        let decoded = image::load_from_memory(&exported.data).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(0, 0)[3], 0);
+        assert_eq!(decoded.get_pixel(199, 119)[3], 255);
+    }
+
+    #[test]
+    fn optimize_keeps_the_pixels() {
+        let options = ExportOptions {
+            optimize: Some(2),
+            ..Default::default()
+        };
+        let exported = export(&screenshot(), ExportFormat::Png, &options).unwrap();
+        let decoded = image::load_from_memory(&exported.data).unwrap().to_rgba8();
+        assert_eq!(decoded, screenshot());
+    }
+}
@@ -0,0 +1,269 @@
+use crate::error::CaptureError;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Where the webview is allowed to have files written.
+///
+/// Writes are accepted inside the configured default folder and to exact
+/// paths the user picked in a save dialog opened from Rust, so a compromised
+/// webview cannot write anywhere else. A picked path outside the folder can
+/// be written once per pick.
+#[derive(Default)]
+pub struct OutputScope {
+    chosen: Mutex<HashSet<PathBuf>>,
+}
+
+impl OutputScope {
+    /// Allows writing to `path`, which the user picked in a dialog.
+    pub fn allow_file(&self, path: PathBuf) {
+        self.chosen.lock().unwrap().insert(path);
+    }
+
+    /// Returns `path` if it may be written, given the current default folder.
+    /// A path allowed with `allow_file` is used up by the first check.
+    pub fn check(
+        &self,
+        path: &Path,
+        default_folder: Option<&Path>,
+    ) -> Result<PathBuf, CaptureError> {
+        let denied = || CaptureError::PathNotAllowed {
+            path: path.to_path_buf(),
+        };
+        if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+            return Err(denied());
+        }
+        if self.chosen.lock().unwrap().remove(path) {
+            return Ok(path.to_path_buf());
+        }
+        let root = default_folder.ok_or_else(denied)?;
+        if !path.starts_with(root) {
+            return Err(denied());
+        }
+        // A symlink inside the folder must not lead back out of it
+        let root = root.canonicalize().map_err(|e| CaptureError::io(root, e))?;
+        let existing = path
+            .ancestors()
+            .skip(1)
+            .find(|p| p.exists())
+            .ok_or_else(denied)?;
+        let existing = existing
+            .canonicalize()
+            .map_err(|e| CaptureError::io(existing, e))?;
+        if !existing.starts_with(&root) {
+            return Err(denied());
+        }
+        Ok(path.to_path_buf())
+    }
+}
+
+/// Returns `extension` without a leading dot if it is 1-8 ASCII letters or
+/// digits, so it cannot lead out of the folder of the name it ends.
+pub fn check_extension(extension: &str) -> Result<&str, CaptureError> {
+    let trimmed = extension.trim_start_matches('.');
+    if !(1..=8).contains(&trimmed.len()) || !trimmed.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(CaptureError::invalid(format!(
+            "Invalid extension {:?}",
+            extension
+        )));
+    }
+    Ok(trimmed)
+}
+
+/// Writes `data` to `path` through a temporary file in the same directory
+/// that is renamed into place, so readers never see a partial file. Missing
+/// parent directories are created. With `overwrite` false an existing file
+/// is left alone and `FileExists` is returned.
+pub fn write_atomic(path: &Path, data: &[u8], overwrite: bool) -> Result<(), CaptureError> {
+    let dir = path
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .ok_or_else(|| CaptureError::invalid(format!("{} has no folder", path.display())))?;
+    fs::create_dir_all(dir).map_err(|e| CaptureError::io(dir, e))?;
+
+    let mut temp = tempfile::Builder::new()
+        .prefix(".capture-")
+        .suffix(".tmp")
+        .tempfile_in(dir)
+        .map_err(|e| CaptureError::io(dir, e))?;
+    temp.write_all(data)
+        .and_then(|_| temp.as_file().sync_all())
+        .map_err(|e| CaptureError::io(temp.path(), e))?;
+
+    let persisted = if overwrite {
+        temp.persist(path)
+    } else {
+        temp.persist_noclobber(path)
+    };
+    persisted.map(|_| ()).map_err(|e| {
+        if e.error.kind() == std::io::ErrorKind::AlreadyExists {
+            CaptureError::FileExists {
+                path: path.to_path_buf(),
+            }
+        } else {
+            CaptureError::io(path, e.error)
+        }
+    })
+}
+
+/// Moves a finished file to `path`, creating missing parent directories.
+/// Falls back to copying when `path` is on another filesystem. With
+/// `overwrite` false an existing file is left alone and `FileExists` is
+/// returned.
+pub fn move_file(from: &Path, path: &Path, overwrite: bool) -> Result<(), CaptureError> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| CaptureError::io(dir, e))?;
+    }
+    // A hard link fails instead of replacing, which gives an atomic
+    // no-overwrite move
+    let moved = if overwrite {
+        fs::rename(from, path)
+    } else {
+        fs::hard_link(from, path).and_then(|_| fs::remove_file(from))
+    };
+    match moved {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Err(CaptureError::FileExists {
+            path: path.to_path_buf(),
+        }),
+        Err(_) => {
+            let data = fs::read(from).map_err(|e| CaptureError::io(from, e))?;
+            write_atomic(path, &data, overwrite)?;
+            fs::remove_file(from).map_err(|e| CaptureError::io(from, e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        (dir, root)
+    }
+
+    fn is_denied(result: Result<PathBuf, CaptureError>) -> bool {
+        matches!(result, Err(CaptureError::PathNotAllowed { .. }))
+    }
+
+    #[test]
+    fn allows_paths_inside_the_folder() {
+        let (_dir, root) = folder();
+        let scope = OutputScope::default();
+        let path = root.join("shots").join("a.png");
+        assert_eq!(scope.check(&path, Some(&root)).unwrap(), path);
+        assert!(is_denied(scope.check(&path, None)));
+    }
+
+    #[test]
+    fn rejects_parent_components() {
+        let (_dir, root) = folder();
+        let scope = OutputScope::default();
+        let path = root.join("shots").join("..").join("..").join("a.png");
+        assert!(is_denied(scope.check(&path, Some(&root))));
+        // Even when the user picked it
+        scope.allow_file(path.clone());
+        assert!(is_denied(scope.check(&path, Some(&root))));
+    }
+
+    #[test]
+    fn rejects_relative_paths() {
+        let (_dir, root) = folder();
+        let scope = OutputScope::default();
+        assert!(is_denied(scope.check(Path::new("a.png"), Some(&root))));
+        scope.allow_file(PathBuf::from("b.png"));
+        assert!(is_denied(scope.check(Path::new("b.png"), Some(&root))));
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_folder() {
+        let (_dir, root) = folder();
+        let (_other, outside) = folder();
+        let scope = OutputScope::default();
+        assert!(is_denied(scope.check(&outside.join("a.png"), Some(&root))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_out_of_the_folder() {
+        let (_dir, root) = folder();
+        let (_other, outside) = folder();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+        let scope = OutputScope::default();
+        let path = root.join("link").join("a.png");
+        assert!(is_denied(scope.check(&path, Some(&root))));
+        let path = root.join("link").join("missing").join("a.png");
+        assert!(is_denied(scope.check(&path, Some(&root))));
+    }
+
+    #[test]
+    fn chosen_files_are_allowed_once() {
+        let (_dir, root) = folder();
+        let (_other, outside) = folder();
+        let scope = OutputScope::default();
+        let path = outside.join("picked.png");
+        scope.allow_file(path.clone());
+        assert_eq!(scope.check(&path, Some(&root)).unwrap(), path);
+        assert!(is_denied(scope.check(&path, Some(&root))));
+        assert!(is_denied(scope.check(&path, None)));
+    }
+
+    #[test]
+    fn write_atomic_creates_folders_and_replaces() {
+        let (_dir, root) = folder();
+        let path = root.join("a").join("b.txt");
+        write_atomic(&path, b"one", true).unwrap();
+        write_atomic(&path, b"two", true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"two");
+    }
+
+    #[test]
+    fn write_atomic_keeps_existing_files() {
+        let (_dir, root) = folder();
+        let path = root.join("b.txt");
+        fs::write(&path, b"old").unwrap();
+        let result = write_atomic(&path, b"new", false);
+        assert!(matches!(result, Err(CaptureError::FileExists { .. })));
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn failed_writes_leave_no_temp_file() {
+        let (_dir, root) = folder();
+        // Renaming a file over a directory fails after the temp file is written
+        let path = root.join("taken");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inner"), b"x").unwrap();
+        assert!(write_atomic(&path, b"data", true).is_err());
+        let names: Vec<_> = fs::read_dir(&root)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["taken"]);
+    }
+
+    #[test]
+    fn move_file_respects_overwrite() {
+        let (_dir, root) = folder();
+        let from = root.join("part");
+        let path = root.join("out").join("video.webm");
+        fs::write(&from, b"first").unwrap();
+        move_file(&from, &path, false).unwrap();
+        assert!(!from.exists());
+
+        fs::write(&from, b"second").unwrap();
+        let result = move_file(&from, &path, false);
+        assert!(matches!(result, Err(CaptureError::FileExists { .. })));
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert!(from.exists());
+
+        move_file(&from, &path, true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!from.exists());
+    }
+}
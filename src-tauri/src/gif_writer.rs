@@ -0,0 +1,388 @@
+use crate::error::CaptureError;
+use crate::optimize;
+use color_quant::NeuQuant;
+use gif::{DisposalMethod, Encoder, Frame, Repeat};
+use image::{imageops, RgbaImage};
+use std::borrow::Cow;
+
+/// Browsers show delays under 2 centiseconds as 10, so never go below it.
+const MIN_DELAY_CS: u16 = 2;
+
+/// Where frame palettes come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Palette {
+    /// One palette for the whole file, built from the first frame. Smaller,
+    /// and colors stay stable across frames.
+    #[default]
+    Global,
+    /// A palette per frame, built from the pixels that changed. Better for
+    /// content whose colors change a lot over the recording.
+    PerFrame,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Dither {
+    None,
+    /// Bayer pattern. Stable across frames, so it compresses well.
+    #[default]
+    Ordered,
+    /// Floyd-Steinberg. Smoothest gradients, but the pattern shifts
+    /// whenever anything above it changes.
+    ErrorDiffusion,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GifOptions {
+    pub palette: Palette,
+    pub dither: Dither,
+    /// Palette size, 2-256.
+    pub colors: u16,
+    /// NeuQuant sampling factor, 1 (best) to 30 (fastest).
+    pub speed: u8,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        Self {
+            palette: Palette::Global,
+            dither: Dither::Ordered,
+            colors: 256,
+            speed: 10,
+        }
+    }
+}
+
+impl GifOptions {
+    pub fn validate(&self) -> Result<(), CaptureError> {
+        if !(2..=256).contains(&self.colors) {
+            return Err(CaptureError::invalid("colors must be between 2 and 256"));
+        }
+        if !(1..=30).contains(&self.speed) {
+            return Err(CaptureError::invalid("speed must be between 1 and 30"));
+        }
+        Ok(())
+    }
+}
+
+/// Smallest rectangle `(x, y, width, height)` holding every pixel that
+/// differs between the two frames, or `None` if they are identical.
+fn changed_rect(previous: &RgbaImage, current: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    let width = current.width() as usize;
+    let rows_differ = |y: usize| {
+        let range = y * width * 4..(y + 1) * width * 4;
+        previous.as_raw()[range.clone()] != current.as_raw()[range]
+    };
+    let top = (0..current.height() as usize).find(|&y| rows_differ(y))?;
+    let bottom = (top..current.height() as usize)
+        .rev()
+        .find(|&y| rows_differ(y))
+        .unwrap_or(top);
+
+    let (mut left, mut right) = (width, 0);
+    for y in top..=bottom {
+        for x in 0..width {
+            let (x32, y32) = (x as u32, y as u32);
+            if previous.get_pixel(x32, y32) != current.get_pixel(x32, y32) {
+                left = left.min(x);
+                right = right.max(x);
+            }
+        }
+    }
+    Some((
+        left as u32,
+        top as u32,
+        (right - left + 1) as u32,
+        (bottom - top + 1) as u32,
+    ))
+}
+
+fn rgb_palette(quant: &NeuQuant) -> Vec<u8> {
+    quant
+        .color_map_rgba()
+        .chunks_exact(4)
+        .flat_map(|c| [c[0], c[1], c[2]])
+        .collect()
+}
+
+/// A frame waiting for the next one, which decides how long it is shown.
+struct Pending {
+    frame: Frame<'static>,
+    timestamp_cs: u64,
+}
+
+/// Writes an animated GIF one frame at a time.
+///
+/// Each frame only covers the rectangle that changed since the previous
+/// one and is drawn over it, and frames identical to the previous one are
+/// not written at all; the previous frame is shown longer instead.
+pub struct GifWriter {
+    options: GifOptions,
+    encoder: Option<Encoder<Vec<u8>>>,
+    /// The global palette, when one is used.
+    global: Option<NeuQuant>,
+    previous: Option<RgbaImage>,
+    pending: Option<Pending>,
+    last_delay: u16,
+}
+
+impl GifWriter {
+    pub fn new(options: GifOptions) -> Self {
+        Self {
+            options,
+            encoder: None,
+            global: None,
+            previous: None,
+            pending: None,
+            last_delay: 10,
+        }
+    }
+
+    fn quantizer(&self, pixels: &RgbaImage) -> NeuQuant {
+        NeuQuant::new(
+            self.options.speed as i32,
+            self.options.colors as usize,
+            pixels.as_raw(),
+        )
+    }
+
+    /// Adds a frame shown from `timestamp_ms` on and returns the bytes to
+    /// append. All frames must have the size of the first.
+    pub fn frame(&mut self, image: &RgbaImage, timestamp_ms: u64) -> Result<Vec<u8>, CaptureError> {
+        let (x, y, width, height) = match &self.previous {
+            Some(previous) => match changed_rect(previous, image) {
+                Some(rect) => rect,
+                None => return Ok(Vec::new()),
+            },
+            None => (0, 0, image.width(), image.height()),
+        };
+
+        if self.encoder.is_none() {
+            let global = match self.options.palette {
+                Palette::Global => Some(self.quantizer(image)),
+                Palette::PerFrame => None,
+            };
+            let palette = global.as_ref().map(rgb_palette).unwrap_or_default();
+            let mut encoder = Encoder::new(
+                Vec::new(),
+                image.width() as u16,
+                image.height() as u16,
+                &palette,
+            )
+            .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+            encoder
+                .set_repeat(Repeat::Infinite)
+                .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+            self.encoder = Some(encoder);
+            self.global = global;
+        }
+
+        let changed = imageops::crop_imm(image, x, y, width, height).to_image();
+        let local = match self.options.palette {
+            Palette::Global => None,
+            Palette::PerFrame => Some(self.quantizer(&changed)),
+        };
+        let quant = local
+            .as_ref()
+            .or(self.global.as_ref())
+            .expect("the global palette is built with the encoder");
+        let indices = match self.options.dither {
+            Dither::None => optimize::dither(&changed, quant, 0.0),
+            Dither::Ordered => optimize::dither_ordered(&changed, quant, (x, y)),
+            Dither::ErrorDiffusion => optimize::dither(&changed, quant, 1.0),
+        };
+
+        let frame = Frame {
+            dispose: DisposalMethod::Keep,
+            left: x as u16,
+            top: y as u16,
+            width: width as u16,
+            height: height as u16,
+            palette: local.as_ref().map(rgb_palette),
+            buffer: Cow::Owned(indices),
+            ..Frame::default()
+        };
+        let timestamp_cs = timestamp_ms / 10;
+        let out = match self.pending.take() {
+            Some(pending) => {
+                let delay = timestamp_cs.saturating_sub(pending.timestamp_cs);
+                self.write(pending.frame, delay.min(u16::MAX as u64) as u16)?
+            }
+            None => Vec::new(),
+        };
+        self.pending = Some(Pending {
+            frame,
+            timestamp_cs,
+        });
+        self.previous = Some(image.clone());
+        Ok(out)
+    }
+
+    fn write(&mut self, mut frame: Frame<'static>, delay: u16) -> Result<Vec<u8>, CaptureError> {
+        frame.delay = delay.max(MIN_DELAY_CS);
+        self.last_delay = frame.delay;
+        let Some(encoder) = self.encoder.as_mut() else {
+            return Ok(Vec::new());
+        };
+        encoder
+            .write_frame(&frame)
+            .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+        Ok(std::mem::take(encoder.get_mut()))
+    }
+
+    /// Writes the last frame, shown as long as the one before it, and the
+    /// GIF trailer.
+    pub fn finish(&mut self) -> Result<Vec<u8>, CaptureError> {
+        let mut out = match self.pending.take() {
+            Some(pending) => self.write(pending.frame, self.last_delay)?,
+            None => Vec::new(),
+        };
+        if let Some(encoder) = self.encoder.take() {
+            let rest = encoder
+                .into_inner()
+                .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+            out.extend(rest);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// Position, size, delay and whether the frame has its own palette.
+    type FrameInfo = (u16, u16, u16, u16, u16, bool);
+
+    fn encode(options: GifOptions, frames: &[(&RgbaImage, u64)]) -> Vec<u8> {
+        let mut writer = GifWriter::new(options);
+        let mut data = Vec::new();
+        for (image, timestamp_ms) in frames {
+            data.extend(writer.frame(image, *timestamp_ms).unwrap());
+        }
+        data.extend(writer.finish().unwrap());
+        data
+    }
+
+    /// The global palette's length in bytes and every frame in the file.
+    fn decode(data: &[u8]) -> (Option<usize>, Vec<FrameInfo>) {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(data).unwrap();
+        let global = decoder.global_palette().map(<[u8]>::len);
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((
+                frame.left,
+                frame.top,
+                frame.width,
+                frame.height,
+                frame.delay,
+                frame.palette.is_some(),
+            ));
+        }
+        (global, frames)
+    }
+
+    fn background() -> RgbaImage {
+        RgbaImage::from_fn(64, 48, |x, y| Rgba([(x * 4) as u8, (y * 5) as u8, 90, 255]))
+    }
+
+    /// `background` with a solid block over the given rectangle.
+    fn with_block(x: u32, y: u32, width: u32, height: u32) -> RgbaImage {
+        let mut image = background();
+        for (px, py, pixel) in image.enumerate_pixels_mut() {
+            if (x..x + width).contains(&px) && (y..y + height).contains(&py) {
+                *pixel = Rgba([255, 255, 255, 255]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn changed_rect_bounds_the_differences() {
+        let first = background();
+        assert_eq!(changed_rect(&first, &first), None);
+        let second = with_block(10, 5, 4, 3);
+        assert_eq!(changed_rect(&first, &second), Some((10, 5, 4, 3)));
+        let mut third = second.clone();
+        third.put_pixel(63, 47, Rgba([0, 0, 0, 255]));
+        assert_eq!(changed_rect(&second, &third), Some((63, 47, 1, 1)));
+    }
+
+    #[test]
+    fn frames_cover_only_what_changed() {
+        let first = background();
+        let second = with_block(10, 5, 4, 3);
+        let third = with_block(10, 5, 20, 30);
+        let data = encode(
+            GifOptions::default(),
+            &[(&first, 0), (&second, 100), (&third, 200)],
+        );
+        let (_, frames) = decode(&data);
+        let rects: Vec<_> = frames.iter().map(|f| (f.0, f.1, f.2, f.3)).collect();
+        assert_eq!(rects, [(0, 0, 64, 48), (10, 5, 4, 3), (10, 5, 20, 30)]);
+    }
+
+    #[test]
+    fn identical_frames_extend_the_previous_delay() {
+        let first = background();
+        let second = with_block(0, 0, 8, 8);
+        let third = with_block(0, 0, 16, 16);
+        let data = encode(
+            GifOptions::default(),
+            &[(&first, 0), (&second, 200), (&second, 250), (&third, 300)],
+        );
+        let (_, frames) = decode(&data);
+        let delays: Vec<u16> = frames.iter().map(|f| f.4).collect();
+        assert_eq!(delays, [20, 10, 10]);
+    }
+
+    #[test]
+    fn short_delays_are_raised_to_the_minimum() {
+        let first = background();
+        let second = with_block(0, 0, 8, 8);
+        let data = encode(GifOptions::default(), &[(&first, 0), (&second, 5)]);
+        let (_, frames) = decode(&data);
+        let delays: Vec<u16> = frames.iter().map(|f| f.4).collect();
+        assert_eq!(delays, [MIN_DELAY_CS, MIN_DELAY_CS]);
+    }
+
+    #[test]
+    fn global_palette_comes_from_the_first_frame() {
+        let first = background();
+        let second = with_block(10, 5, 4, 3);
+        let options = GifOptions {
+            colors: 64,
+            ..Default::default()
+        };
+        let data = encode(options, &[(&first, 0), (&second, 100)]);
+        let (global, frames) = decode(&data);
+        assert_eq!(global, Some(64 * 3));
+        assert!(frames.iter().all(|f| !f.5));
+
+        // The first frame is drawn with exactly the palette built from it
+        let quant = NeuQuant::new(10, 64, first.as_raw());
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let decoder = options.read_info(data.as_slice()).unwrap();
+        assert_eq!(decoder.global_palette().unwrap(), rgb_palette(&quant));
+    }
+
+    #[test]
+    fn per_frame_palettes_are_local() {
+        let first = background();
+        let second = with_block(10, 5, 4, 3);
+        let options = GifOptions {
+            palette: Palette::PerFrame,
+            ..Default::default()
+        };
+        let data = encode(options, &[(&first, 0), (&second, 100)]);
+        let (_, frames) = decode(&data);
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|f| f.5));
+    }
+}
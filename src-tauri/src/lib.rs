@@ -1,284 +1,1596 @@
+mod backend;
+mod error;
+mod export;
+mod files;
+mod gif_writer;
+mod matching;
+mod naming;
+mod optimize;
+mod recording;
+mod region;
+mod replay;
+mod settings;
+mod shortcuts;
+mod store;
+mod stream;
+mod thumbnails;
+mod video_files;
+mod webm;
+
+use backend::{CaptureBackend, MonitorInfo, WindowInfo};
 use base64::{engine::general_purpose, Engine as _};
-use image::{DynamicImage, ImageFormat};
+use error::CaptureError;
+use export::{ExportFormat, ExportOptions};
+use files::OutputScope;
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::{ImageFormat, RgbaImage};
+use matching::{Pattern, Pick};
 use mouse_position::mouse_position::Mouse;
-use std::fs;
+use naming::{Collision, FileKind, NameFields};
+use percent_encoding::percent_decode_str;
+use recording::{
+    Recorder, RecordingOptions, RecordingSlot, RecordingState, RecordingStats, StopReason,
+};
+use region::MonitorPlacement;
+use replay::{ReplayBuffer, ReplayOptions, ReplayStatus, StoredFrame};
+use settings::{Settings, SettingsStore};
+use shortcuts::{Keymap, KeymapState, KeymapStatus, ShortcutAction};
 use std::io::Cursor;
-use tauri_plugin_global_shortcut::{Code, Modifiers, Shortcut, ShortcutState};
-use xcap::{Monitor, Window};
+use std::path::{Path, PathBuf};
+use store::{CaptureMeta, CaptureSource, CaptureStore};
+use stream::{
+    FrameQueue, RepeatFilter, StreamHandle, StreamInfo, StreamPurpose, StreamRegistry, StreamStats,
+};
+use tauri::http;
+use tauri::ipc::{Channel, InvokeBody, InvokeResponseBody, JavaScriptChannelId, Request, Response};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use tauri_plugin_dialog::DialogExt;
+use tauri_plugin_global_shortcut::ShortcutState;
+use thumbnails::{ThumbnailCache, WindowThumbnails};
+use video_files::VideoFiles;
 
-#[derive(serde::Serialize)]
-struct WindowInfo {
-    id: u32,
-    title: String,
-    app_name: String,
+// Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+#[tauri::command]
+fn get_monitors(state: State<'_, AppState>) -> Result<Vec<MonitorInfo>, CaptureError> {
+    state.backend.monitors()
+}
+
+fn encode_png(image: &RgbaImage) -> Result<Vec<u8>, CaptureError> {
+    let mut buffer = Vec::new();
+    let mut cursor = Cursor::new(&mut buffer);
+    image.write_to(&mut cursor, ImageFormat::Png)?;
+    Ok(buffer)
+}
+
+/// How binary results are returned to the webview. `Raw` goes over the
+/// binary IPC path (an `ArrayBuffer` in JS); `Base64` keeps the JSON string
+/// older callers expect.
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    #[default]
+    Raw,
+    Base64,
+}
+
+fn into_response(bytes: Vec<u8>, encoding: Option<Encoding>) -> Result<Response, CaptureError> {
+    match encoding.unwrap_or_default() {
+        Encoding::Raw => Ok(Response::new(bytes)),
+        Encoding::Base64 => {
+            let encoded = general_purpose::STANDARD.encode(&bytes);
+            let json = serde_json::to_string(&encoded)
+                .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+            Ok(Response::new(json))
+        }
+    }
+}
+
+#[tauri::command]
+fn capture_screen(
+    state: State<'_, AppState>,
+    monitor_id: Option<u32>,
+    encoding: Option<Encoding>,
+) -> Result<Response, CaptureError> {
+    let monitor = backend::resolve_monitor(state.backend.as_ref(), monitor_id)?;
+    let image = state.backend.capture_monitor(monitor.id)?;
+    into_response(encode_png(&image)?, encoding)
+}
+
+/// Captures part of a monitor. The rectangle is in the monitor's captured
+/// pixels, so it is independent of the webview's devicePixelRatio.
+#[tauri::command]
+fn capture_region(
+    state: State<'_, AppState>,
+    monitor_id: Option<u32>,
+    x: u32,
+    y: u32,
     width: u32,
     height: u32,
+    encoding: Option<Encoding>,
+) -> Result<Response, CaptureError> {
+    let monitor = backend::resolve_monitor(state.backend.as_ref(), monitor_id)?;
+    let image = state.backend.capture_monitor(monitor.id)?;
+    let cropped = region::crop(image, x, y, width, height)?;
+    *state.last_region.lock().unwrap() = Some(CaptureSource::Region {
+        monitor_id: Some(monitor.id),
+        x,
+        y,
+        width,
+        height,
+    });
+    into_response(encode_png(&cropped)?, encoding)
 }
 
-#[derive(serde::Serialize)]
-struct MonitorInfo {
-    id: u32,
-    name: String,
+/// Captures a rectangle in virtual-desktop coordinates, which may span
+/// several monitors.
+#[tauri::command]
+fn capture_desktop_region(
+    state: State<'_, AppState>,
+    x: i32,
+    y: i32,
     width: u32,
     height: u32,
-    is_primary: bool,
+    encoding: Option<Encoding>,
+) -> Result<Response, CaptureError> {
+    let image = region::capture_desktop_region(state.backend.as_ref(), x, y, width, height)?;
+    into_response(encode_png(&image)?, encoding)
 }
 
-// Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+#[derive(serde::Serialize)]
+struct AllMonitorsCapture {
+    capture: CaptureMeta,
+    layout: Vec<MonitorPlacement>,
+}
+
+/// Stitches every monitor into one stored capture and returns where each
+/// monitor sits inside it.
 #[tauri::command]
-fn get_monitors() -> Result<Vec<MonitorInfo>, String> {
-    let monitors = Monitor::all().map_err(|e| e.to_string())?;
-    let monitor_infos = monitors
+fn capture_all_monitors(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<AllMonitorsCapture, CaptureError> {
+    let (image, layout) = region::capture_all_monitors(state.backend.as_ref())?;
+    let capture = store_capture(&app, image, CaptureSource::AllMonitors, None);
+    Ok(AllMonitorsCapture { capture, layout })
+}
+
+/// Lists capturable windows.
+///
+/// Windows smaller than `min_size` (default 50px) in either dimension are
+/// dropped as likely system overlays; pass 0 to keep everything. Minimized
+/// windows, which usually capture black, are skipped when
+/// `include_minimized` is false.
+#[tauri::command]
+fn get_windows(
+    state: State<'_, AppState>,
+    min_size: Option<u32>,
+    include_minimized: Option<bool>,
+) -> Result<Vec<WindowInfo>, CaptureError> {
+    let min_size = min_size.unwrap_or(50);
+    let include_minimized = include_minimized.unwrap_or(true);
+    let windows = state.backend.windows()?;
+    let window_infos = windows
         .into_iter()
-        .map(|m| MonitorInfo {
-            id: m.id().unwrap_or(0),
-            name: m.name().unwrap_or_default(),
-            width: m.width().unwrap_or(0),
-            height: m.height().unwrap_or(0),
-            is_primary: m.is_primary().unwrap_or(false),
-        })
+        .filter(|w| w.width >= min_size && w.height >= min_size)
+        .filter(|w| include_minimized || !w.is_minimized)
         .collect();
-    Ok(monitor_infos)
-}
-
-#[tauri::command]
-fn capture_screen(monitor_id: Option<u32>) -> Result<String, String> {
-    let monitors = Monitor::all().map_err(|e| e.to_string())?;
-    let monitor = if let Some(id) = monitor_id {
-        monitors
-            .iter()
-            .find(|m| m.id().unwrap_or(0) == id)
-            .ok_or("Monitor not found")?
-    } else {
-        monitors
-            .iter()
-            .find(|m| m.is_primary().unwrap_or(false))
-            .or(monitors.first())
-            .ok_or("No monitor found")?
+    Ok(window_infos)
+}
+
+/// Small previews of every listed window, keyed by window ID, for a visual
+/// window picker. `max_size` bounds the longer side (default 240px). Only
+/// sizes are returned; the images load from the `capture` protocol.
+/// Windows that could not be captured are listed in `failures`.
+#[tauri::command]
+fn get_window_thumbnails(
+    state: State<'_, AppState>,
+    max_size: Option<u32>,
+    include_minimized: Option<bool>,
+) -> Result<WindowThumbnails, CaptureError> {
+    let windows = get_windows(state.clone(), None, include_minimized)?;
+    Ok(thumbnails::thumbnails(
+        &state.thumbnails,
+        state.backend.as_ref(),
+        &windows,
+        max_size.unwrap_or(240),
+    ))
+}
+
+/// Captures the focused window, ignoring this app's own windows.
+#[tauri::command]
+fn capture_active_window(
+    state: State<'_, AppState>,
+    encoding: Option<Encoding>,
+) -> Result<Response, CaptureError> {
+    let window = backend::active_window(state.backend.as_ref())?;
+    let image = state.backend.capture_window(window.id)?;
+    into_response(encode_png(&image)?, encoding)
+}
+
+/// Captures the frontmost window under the mouse cursor.
+#[tauri::command]
+fn capture_window_at_cursor(
+    state: State<'_, AppState>,
+    encoding: Option<Encoding>,
+) -> Result<Response, CaptureError> {
+    let window = get_window_at_cursor(state.backend.as_ref())?;
+    let image = state.backend.capture_window(window.id)?;
+    into_response(encode_png(&image)?, encoding)
+}
+
+/// Captures windows selected by app-name and/or title patterns instead of
+/// volatile numeric IDs, storing each capture. `pick` decides what happens
+/// when several windows match; by default that is an `ambiguous_window`
+/// error listing the candidates.
+#[tauri::command]
+fn capture_window_matching(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    app_name: Option<Pattern>,
+    title: Option<Pattern>,
+    pick: Option<Pick>,
+) -> Result<Vec<CaptureMeta>, CaptureError> {
+    let windows = matching::select(
+        state.backend.windows()?,
+        app_name.as_ref(),
+        title.as_ref(),
+        pick.unwrap_or_default(),
+    )?;
+    let mut captures = Vec::with_capacity(windows.len());
+    for window in windows {
+        let image = state.backend.capture_window(window.id)?;
+        let source = CaptureSource::Window { id: window.id };
+        captures.push(store_capture(&app, image, source, None));
+    }
+    Ok(captures)
+}
+
+#[tauri::command]
+fn capture_window(
+    state: State<'_, AppState>,
+    id: u32,
+    encoding: Option<Encoding>,
+) -> Result<Response, CaptureError> {
+    let image = state.backend.capture_window(id)?;
+    into_response(encode_png(&image)?, encoding)
+}
+
+/// Writes `data` to `path` atomically, provided the path is inside the
+/// default folder or was picked in a save dialog.
+fn write_output(
+    state: &AppState,
+    settings: &SettingsStore,
+    path: &str,
+    data: &[u8],
+    overwrite: bool,
+) -> Result<(), CaptureError> {
+    let default_folder = settings.get().default_path.map(PathBuf::from);
+    let path = state
+        .output
+        .check(Path::new(path), default_folder.as_deref())?;
+    files::write_atomic(&path, data, overwrite)
+}
+
+/// Writes an encoded image to disk.
+///
+/// The preferred form sends the file bytes as the raw request body with the
+/// percent-encoded destination in a `path` header and an optional
+/// `overwrite: false` header. A JSON body of `{ path, data, overwrite }`
+/// with base64 `data` is still accepted.
+#[tauri::command]
+fn save_image(
+    state: State<'_, AppState>,
+    settings: State<'_, SettingsStore>,
+    request: Request<'_>,
+) -> Result<(), CaptureError> {
+    let (path, bytes, overwrite) = match request.body() {
+        InvokeBody::Raw(bytes) => {
+            let header = request
+                .headers()
+                .get("path")
+                .ok_or_else(|| CaptureError::invalid("Missing path header"))?
+                .to_str()
+                .map_err(|e| CaptureError::invalid(e.to_string()))?;
+            let path = percent_decode_str(header)
+                .decode_utf8()
+                .map_err(|e| CaptureError::invalid(e.to_string()))?
+                .into_owned();
+            let overwrite = request
+                .headers()
+                .get("overwrite")
+                .is_none_or(|v| v.as_bytes() != b"false");
+            (path, bytes.clone(), overwrite)
+        }
+        InvokeBody::Json(value) => {
+            let path = value["path"]
+                .as_str()
+                .ok_or_else(|| CaptureError::invalid("Missing path"))?
+                .to_string();
+            let data = value["data"]
+                .as_str()
+                .ok_or_else(|| CaptureError::invalid("Missing data"))?;
+            let bytes = general_purpose::STANDARD
+                .decode(data)
+                .map_err(|e| CaptureError::invalid(e.to_string()))?;
+            let overwrite = value["overwrite"].as_bool().unwrap_or(true);
+            (path, bytes, overwrite)
+        }
     };
+    write_output(&state, &settings, &path, &bytes, overwrite)
+}
 
-    let image = monitor.capture_image().map_err(|e| e.to_string())?;
+/// Captures from `source` into the backend store and returns its handle.
+#[tauri::command]
+fn take_capture<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    state: State<'_, AppState>,
+    source: CaptureSource,
+) -> Result<CaptureMeta, CaptureError> {
+    let image = source.capture(state.backend.as_ref())?;
+    Ok(store_capture(&app, image, source, None))
+}
 
-    let dynamic_image = DynamicImage::ImageRgba8(image);
-    let mut buffer = Vec::new();
-    let mut cursor = Cursor::new(&mut buffer);
-    dynamic_image
-        .write_to(&mut cursor, ImageFormat::Png)
-        .map_err(|e| e.to_string())?;
+/// Stores a capture. Captures dropped to make room are announced in a
+/// `captures-evicted` event so the webview can forget their IDs.
+fn store_capture<R: Runtime>(
+    app: &tauri::AppHandle<R>,
+    image: RgbaImage,
+    source: CaptureSource,
+    parent: Option<u64>,
+) -> CaptureMeta {
+    let state = app.state::<AppState>();
+    let (meta, evicted) = state.captures.lock().unwrap().insert(image, source, parent);
+    if !evicted.is_empty() {
+        let _ = app.emit("captures-evicted", evicted);
+    }
+    meta
+}
 
-    let encoded = general_purpose::STANDARD.encode(&buffer);
-    Ok(encoded)
+#[tauri::command]
+fn list_captures(state: State<'_, AppState>) -> Vec<CaptureMeta> {
+    state.captures.lock().unwrap().list()
 }
 
+/// Crops a stored capture into a new stored capture. The rectangle is in the
+/// capture's own pixels.
 #[tauri::command]
-fn get_windows() -> Result<Vec<WindowInfo>, String> {
-    let windows = Window::all().map_err(|e| e.to_string())?;
-    let window_infos = windows
-        .into_iter()
-        .filter_map(|w| {
-            let id = w.id().ok()?;
-            let title = w.title().unwrap_or_default();
-            let app_name = w.app_name().unwrap_or_default();
-            let width = w.width().unwrap_or(0);
-            let height = w.height().unwrap_or(0);
-
-            // Filter out very small windows (likely system overlays or hidden windows)
-            if width < 50 || height < 50 {
-                return None;
+fn crop_capture<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    state: State<'_, AppState>,
+    id: u64,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Result<CaptureMeta, CaptureError> {
+    let (meta, image) = state.captures.lock().unwrap().get(id)?;
+    let cropped = region::crop((*image).clone(), x, y, width, height)?;
+    if let Some(source) = meta.source.cropped(x, y, width, height) {
+        *state.last_region.lock().unwrap() = Some(source);
+    }
+    Ok(store_capture(&app, cropped, meta.source, Some(id)))
+}
+
+/// Returns a stored capture encoded as PNG.
+#[tauri::command]
+fn encode_capture(
+    state: State<'_, AppState>,
+    id: u64,
+    encoding: Option<Encoding>,
+) -> Result<Response, CaptureError> {
+    let (_, image) = state.captures.lock().unwrap().get(id)?;
+    into_response(encode_png(&image)?, encoding)
+}
+
+#[tauri::command]
+fn save_capture(
+    state: State<'_, AppState>,
+    settings: State<'_, SettingsStore>,
+    id: u64,
+    path: String,
+    overwrite: Option<bool>,
+) -> Result<(), CaptureError> {
+    let (_, image) = state.captures.lock().unwrap().get(id)?;
+    write_output(
+        &state,
+        &settings,
+        &path,
+        &encode_png(&image)?,
+        overwrite.unwrap_or(true),
+    )
+}
+
+#[derive(serde::Serialize)]
+struct ExportResult {
+    path: String,
+    format: ExportFormat,
+    bytes: u64,
+    /// Size before PNG quantization/optimization, when either ran.
+    bytes_before: Option<u64>,
+}
+
+/// Fills in template values for a file taken from `source`.
+fn name_fields(
+    backend: &dyn CaptureBackend,
+    mode: String,
+    source: Option<&CaptureSource>,
+    size: Option<(u32, u32)>,
+) -> NameFields {
+    let mut fields = NameFields {
+        mode,
+        width: size.map(|(w, _)| w),
+        height: size.map(|(_, h)| h),
+        ..Default::default()
+    };
+    match source {
+        Some(CaptureSource::Window { id } | CaptureSource::FollowWindow { id, .. }) => {
+            if let Ok(window) = backend
+                .windows()
+                .map(|windows| windows.into_iter().find(|w| w.id == *id))
+            {
+                fields.app = window.as_ref().map(|w| w.app_name.clone());
+                fields.title = window.map(|w| w.title);
             }
+        }
+        Some(CaptureSource::Monitor { monitor_id } | CaptureSource::Region { monitor_id, .. }) => {
+            if let Ok(monitor) = backend::resolve_monitor(backend, *monitor_id) {
+                fields.monitor = Some(monitor.name);
+                if size.is_none() {
+                    fields.width = Some(monitor.width);
+                    fields.height = Some(monitor.height);
+                }
+            }
+        }
+        _ => {}
+    }
+    fields
+}
 
-            Some(WindowInfo {
-                id,
-                title,
-                app_name,
-                width,
-                height,
-            })
-        })
-        .collect();
-    Ok(window_infos)
+/// Builds the destination for a new file from the filename template in the
+/// settings.
+///
+/// With a default folder configured this is a full path, with any
+/// subdirectories the template names already created; otherwise it is just a
+/// file name to suggest in a save dialog. `capture_id` supplies the size and
+/// source of a stored capture; `source` overrides that source, e.g. when the
+/// stored capture is an annotated import.
+#[tauri::command]
+fn next_save_path(
+    state: State<'_, AppState>,
+    settings: State<'_, SettingsStore>,
+    kind: Option<FileKind>,
+    mode: String,
+    extension: String,
+    capture_id: Option<u64>,
+    source: Option<CaptureSource>,
+) -> Result<PathBuf, CaptureError> {
+    let meta = match capture_id {
+        Some(id) => Some(state.captures.lock().unwrap().get(id)?.0),
+        None => None,
+    };
+    let source = source.or_else(|| meta.as_ref().map(|m| m.source.clone()));
+    let size = meta.map(|m| (m.width, m.height));
+    let fields = name_fields(state.backend.as_ref(), mode, source.as_ref(), size);
+    let settings = settings.get();
+    naming::next_path(
+        settings.default_path.as_deref().map(Path::new),
+        settings.template_for(kind.unwrap_or_default()),
+        &fields,
+        &extension,
+        settings.on_collision,
+    )
 }
 
+/// Encodes a stored capture in the requested format and writes it to `path`.
 #[tauri::command]
-fn capture_window(id: u32) -> Result<String, String> {
-    let windows = Window::all().map_err(|e| e.to_string())?;
-    let window = windows
-        .into_iter()
-        .find(|w| w.id().unwrap_or(0) == id)
-        .ok_or("Window not found")?;
-    let image = window.capture_image().map_err(|e| e.to_string())?;
+fn export_image(
+    state: State<'_, AppState>,
+    settings: State<'_, SettingsStore>,
+    id: u64,
+    path: String,
+    options: Option<ExportOptions>,
+    overwrite: Option<bool>,
+) -> Result<ExportResult, CaptureError> {
+    let options = options.unwrap_or_default();
+    let format = options.format_for(Path::new(&path))?;
+    let (_, image) = state.captures.lock().unwrap().get(id)?;
+    let exported = export::export(&image, format, &options)?;
+    write_output(
+        &state,
+        &settings,
+        &path,
+        &exported.data,
+        overwrite.unwrap_or(true),
+    )?;
+    Ok(ExportResult {
+        path,
+        format,
+        bytes: exported.data.len() as u64,
+        bytes_before: exported.unoptimized_size.map(|n| n as u64),
+    })
+}
+
+/// Decodes an image sent as the raw request body (any format the `image`
+/// crate reads) and adds it to the store.
+#[tauri::command]
+fn import_capture(
+    app: tauri::AppHandle,
+    request: Request<'_>,
+) -> Result<CaptureMeta, CaptureError> {
+    let InvokeBody::Raw(bytes) = request.body() else {
+        return Err(CaptureError::invalid("Expected raw image bytes"));
+    };
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| CaptureError::invalid(e.to_string()))?
+        .to_rgba8();
+    Ok(store_capture(&app, image, CaptureSource::Imported, None))
+}
 
-    let dynamic_image = DynamicImage::ImageRgba8(image);
+#[tauri::command]
+fn copy_capture(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    id: u64,
+) -> Result<(), CaptureError> {
+    let (_, image) = state.captures.lock().unwrap().get(id)?;
+    let clipboard_image = tauri::image::Image::new(image.as_raw(), image.width(), image.height());
+    app.clipboard()
+        .write_image(&clipboard_image)
+        .map_err(|e| CaptureError::Clipboard(e.to_string()))
+}
+
+#[tauri::command]
+fn discard_capture(state: State<'_, AppState>, id: u64) -> Result<(), CaptureError> {
+    state.captures.lock().unwrap().remove(id)
+}
+
+/// Serves stored captures to the webview as `capture://localhost/<id>`
+/// (`http://capture.localhost/<id>` on Windows), so previews are loaded by the
+/// `<img>` element instead of passing through IPC. Window thumbnails are
+/// served the same way as `thumbnail-<window id>`.
+fn serve_capture(
+    app: &tauri::AppHandle,
+    request: &http::Request<Vec<u8>>,
+) -> http::Response<Vec<u8>> {
+    let not_found = || {
+        http::Response::builder()
+            .status(http::StatusCode::NOT_FOUND)
+            .body(Vec::new())
+            .unwrap()
+    };
+    let path = request.uri().path().trim_start_matches('/');
+    if let Some(window_id) = path.strip_prefix("thumbnail-") {
+        let state = app.state::<AppState>();
+        let png = window_id
+            .parse::<u32>()
+            .ok()
+            .and_then(|id| state.thumbnails.lock().unwrap().png(id));
+        return match png {
+            Some(png) => http::Response::builder()
+                .header(http::header::CONTENT_TYPE, "image/png")
+                .header(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                .body(png.to_vec())
+                .unwrap(),
+            None => not_found(),
+        };
+    }
+    let Ok(id) = path.parse::<u64>() else {
+        return not_found();
+    };
+    let Ok((_, image)) = app.state::<AppState>().captures.lock().unwrap().get(id) else {
+        return not_found();
+    };
+
+    // Previews favor speed over size; they never leave the machine
     let mut buffer = Vec::new();
-    let mut cursor = Cursor::new(&mut buffer);
-    dynamic_image
-        .write_to(&mut cursor, ImageFormat::Png)
-        .map_err(|e| e.to_string())?;
+    let encoder =
+        PngEncoder::new_with_quality(&mut buffer, CompressionType::Fast, PngFilterType::Sub);
+    if let Err(e) = image.write_with_encoder(encoder) {
+        return http::Response::builder()
+            .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+            .body(e.to_string().into_bytes())
+            .unwrap();
+    }
+    http::Response::builder()
+        .header(http::header::CONTENT_TYPE, "image/png")
+        .header(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(buffer)
+        .unwrap()
+}
 
-    let encoded = general_purpose::STANDARD.encode(&buffer);
-    Ok(encoded)
+/// Writes a whole recording in one call. Long recordings should use
+/// `open_video_file` and `append_video_chunk` instead, which never hold the
+/// video in memory.
+#[tauri::command]
+fn save_video(
+    state: State<'_, AppState>,
+    settings: State<'_, SettingsStore>,
+    path: String,
+    data: Vec<u8>,
+    overwrite: Option<bool>,
+) -> Result<(), CaptureError> {
+    write_output(&state, &settings, &path, &data, overwrite.unwrap_or(true))
 }
 
+/// Starts writing a recording to a temporary file in the app's cache folder
+/// and returns its session ID. The destination is chosen when finalizing, so
+/// the user can still pick it after recording.
 #[tauri::command]
-fn save_image(path: String, data: String) -> Result<(), String> {
-    let bytes = general_purpose::STANDARD
-        .decode(data)
-        .map_err(|e| e.to_string())?;
-    fs::write(path, bytes).map_err(|e| e.to_string())?;
-    Ok(())
+fn open_video_file(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    extension: Option<String>,
+) -> Result<u64, CaptureError> {
+    state.video_files.lock().unwrap().open(
+        &recordings_dir(&app)?,
+        extension.as_deref().unwrap_or("webm"),
+    )
+}
+
+/// Where recordings are written until they are finalized.
+fn recordings_dir(app: &tauri::AppHandle) -> Result<PathBuf, CaptureError> {
+    Ok(app
+        .path()
+        .app_cache_dir()
+        .map_err(|e| CaptureError::invalid(e.to_string()))?
+        .join("recordings"))
 }
 
+/// Appends the raw request body to a recording. The session ID goes in a
+/// `session` header. Returns the bytes written so far.
 #[tauri::command]
-fn save_video(path: String, data: Vec<u8>) -> Result<(), String> {
-    fs::write(path, data).map_err(|e| e.to_string())?;
-    Ok(())
+fn append_video_chunk(
+    state: State<'_, AppState>,
+    request: Request<'_>,
+) -> Result<u64, CaptureError> {
+    let InvokeBody::Raw(bytes) = request.body() else {
+        return Err(CaptureError::invalid("Expected raw video bytes"));
+    };
+    let id = request
+        .headers()
+        .get("session")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| CaptureError::invalid("Missing session header"))?;
+    state.video_files.lock().unwrap().append(id, bytes)
 }
 
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
+#[derive(serde::Serialize)]
+struct VideoFileResult {
+    path: String,
+    bytes: u64,
+}
+
+/// Closes a recording and moves it to `path`, which must be inside the
+/// default folder or picked in a save dialog. If the move fails the session
+/// is kept, so it can be finalized again or aborted.
+#[tauri::command]
+fn finalize_video_file(
+    state: State<'_, AppState>,
+    settings: State<'_, SettingsStore>,
+    session: u64,
+    path: String,
+    overwrite: Option<bool>,
+) -> Result<VideoFileResult, CaptureError> {
+    let default_folder = settings.get().default_path.map(PathBuf::from);
+    let destination = state
+        .output
+        .check(Path::new(&path), default_folder.as_deref())?;
+    let (part, bytes) = state.video_files.lock().unwrap().finish(session)?;
+    // On failure the session stays, so the caller can retry or abort it
+    files::move_file(&part, &destination, overwrite.unwrap_or(true))?;
+    state.video_files.lock().unwrap().remove(session);
+    Ok(VideoFileResult { path, bytes })
+}
+
+/// Closes a recording and deletes it, e.g. when the save dialog is cancelled.
+#[tauri::command]
+fn abort_video_file(state: State<'_, AppState>, session: u64) -> Result<(), CaptureError> {
+    state.video_files.lock().unwrap().abort(session)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DialogFilter {
+    name: String,
+    extensions: Vec<String>,
+}
+
+/// Shows a save dialog and allows writing to the chosen path. Returns `None`
+/// when the user cancels. `default_path` may be a full path or a file name.
+#[tauri::command]
+async fn choose_save_path(
+    app: tauri::AppHandle,
+    default_path: Option<String>,
+    filters: Option<Vec<DialogFilter>>,
+) -> Result<Option<PathBuf>, CaptureError> {
+    let mut dialog = app.dialog().file();
+    if let Some(default_path) = default_path.as_deref().map(Path::new) {
+        if let Some(dir) = default_path.parent().filter(|d| d.is_absolute()) {
+            dialog = dialog.set_directory(dir);
+        }
+        if let Some(name) = default_path.file_name() {
+            dialog = dialog.set_file_name(name.to_string_lossy());
+        }
+    }
+    for filter in filters.unwrap_or_default() {
+        let extensions: Vec<&str> = filter.extensions.iter().map(String::as_str).collect();
+        dialog = dialog.add_filter(filter.name, &extensions);
+    }
+    let Some(chosen) = dialog.blocking_save_file() else {
+        return Ok(None);
+    };
+    let path = chosen
+        .into_path()
+        .map_err(|e| CaptureError::invalid(e.to_string()))?;
+    app.state::<AppState>().output.allow_file(path.clone());
+    Ok(Some(path))
+}
+
+/// Lets the user pick the default save folder, which becomes writable.
+/// Returns the updated settings, or `None` when the user cancels.
+#[tauri::command]
+async fn choose_default_folder(app: tauri::AppHandle) -> Result<Option<Settings>, CaptureError> {
+    let Some(chosen) = app.dialog().file().blocking_pick_folder() else {
+        return Ok(None);
+    };
+    let path = chosen
+        .into_path()
+        .map_err(|e| CaptureError::invalid(e.to_string()))?;
+    let mut patch = serde_json::Map::new();
+    patch.insert(
+        "defaultPath".to_string(),
+        serde_json::Value::from(path.to_string_lossy().into_owned()),
+    );
+    let updated = app.state::<SettingsStore>().update(patch)?;
+    let _ = app.emit("settings-changed", &updated);
+    Ok(Some(updated))
+}
+
+use std::sync::{atomic::Ordering, Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use tauri::{
     menu::{Menu, MenuItem},
     tray::TrayIconBuilder,
-    Emitter, Manager, State,
+    Emitter, Manager, Runtime, State,
 };
 
 struct AppState {
-    is_streaming: Arc<AtomicBool>,
+    backend: Arc<dyn CaptureBackend>,
+    captures: Mutex<CaptureStore>,
+    thumbnails: Mutex<ThumbnailCache>,
+    output: OutputScope,
+    video_files: Mutex<VideoFiles>,
+    streams: Mutex<StreamRegistry>,
+    /// Native recording fed by a recording stream.
+    recording: Mutex<RecordingSlot>,
+    /// Recent frames kept for `save_replay`, with the ID of the stream
+    /// filling them.
+    replay: Mutex<Option<(u64, ReplayBuffer)>>,
+    /// Most recent monitor region captured or cropped, for the
+    /// repeat-last-region shortcut.
+    last_region: Mutex<Option<CaptureSource>>,
+}
+
+impl AppState {
+    fn new(backend: Arc<dyn CaptureBackend>) -> Self {
+        Self {
+            backend,
+            captures: Mutex::new(CaptureStore::default()),
+            thumbnails: Mutex::new(ThumbnailCache::default()),
+            output: OutputScope::default(),
+            video_files: Mutex::new(VideoFiles::default()),
+            streams: Mutex::new(StreamRegistry::default()),
+            recording: Mutex::new(RecordingSlot::default()),
+            replay: Mutex::new(None),
+            last_region: Mutex::new(None),
+        }
+    }
 }
 
+/// Width of the preview frames sent while recording.
+const PREVIEW_WIDTH: u32 = 640;
+
+/// Starts streaming `source`, or `monitor_id` if no source is given, at
+/// `fps` frames per second (default 30) and returns the stream's ID. Any
+/// number of streams can run at once.
 #[tauri::command]
 fn start_streaming(
     window: tauri::Window,
+    webview: tauri::Webview,
     state: State<'_, AppState>,
     monitor_id: Option<u32>,
-) -> Result<(), String> {
-    let is_streaming = state.is_streaming.clone();
-    if is_streaming.load(Ordering::SeqCst) {
-        return Ok(());
-    }
-    is_streaming.store(true, Ordering::SeqCst);
+    source: Option<CaptureSource>,
+    fps: Option<u32>,
+    on_frame: Option<JavaScriptChannelId>,
+) -> Result<u64, CaptureError> {
+    let source = source.unwrap_or(CaptureSource::Monitor { monitor_id });
+    let (info, handle) = add_stream(&state, source, fps.unwrap_or(30), StreamPurpose::View)?;
+    let id = info.id;
+    let target = match on_frame {
+        Some(channel) => FrameTarget::Channel(channel.channel_on(webview)),
+        None => FrameTarget::Event,
+    };
+    spawn_stream(window, info, handle, target);
+    Ok(id)
+}
 
-    let is_streaming_clone = is_streaming.clone();
+fn add_stream(
+    state: &AppState,
+    source: CaptureSource,
+    fps: u32,
+    purpose: StreamPurpose,
+) -> Result<(StreamInfo, StreamHandle), CaptureError> {
+    let source = stream::pin_source(state.backend.as_ref(), source)?;
+    state.streams.lock().unwrap().add(source, fps, purpose)
+}
 
-    thread::spawn(move || {
-        let monitors = match Monitor::all() {
-            Ok(m) => m,
-            Err(e) => {
-                println!("Error getting monitors: {}", e);
-                return;
+/// Where a stream sends frames for display.
+enum FrameTarget {
+    /// Raw JPEG bytes over an IPC channel.
+    Channel(Channel),
+    /// Base64 JPEG in `screen-frame` events.
+    Event,
+    /// Nothing is shown, e.g. while a replay buffer fills in the background.
+    Hidden,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScreenFrame {
+    stream_id: u64,
+    data: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StreamStopped {
+    stream_id: u64,
+}
+
+/// A failure while running a stream, sent in a `stream-error` event. One
+/// that repeats every frame is sent once; `get_stream_stats` counts them all.
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StreamError {
+    stream_id: u64,
+    code: &'static str,
+    message: String,
+}
+
+fn report_stream_error(app: &tauri::AppHandle, stream_id: u64, error: &CaptureError) {
+    let _ = app.emit(
+        "stream-error",
+        StreamError {
+            stream_id,
+            code: error.code(),
+            message: error.to_string(),
+        },
+    );
+}
+
+/// Frames waiting to be encoded for display. Kept short so the webview
+/// sees recent frames rather than a growing backlog.
+const DISPLAY_QUEUE_LEN: usize = 2;
+
+/// A frame queued for display, with its JPEG quality and width limit.
+type DisplayFrame = (RgbaImage, u8, Option<u32>);
+
+/// Frames waiting to be compressed into the replay buffer.
+const REPLAY_QUEUE_LEN: usize = 4;
+
+/// Runs a stream on two threads until `handle.running` is cleared: one
+/// captures at the stream's frame rate, the other encodes JPEG frames and
+/// sends them to `target`. Between them is a short queue that drops its
+/// oldest frame when encoding or the webview falls behind, so capture keeps
+/// its pace. Failures are sent in `stream-error` events.
+///
+/// Recording and replay streams hand every frame to the recording or replay
+/// buffer they were started for, and stop by themselves once it is gone; a
+/// recording stream only displays small previews, at the recording's preview
+/// rate. Replay frames are compressed on their own thread, behind a queue
+/// like the display one. Stopping a stream that still feeds a recording stops the recording,
+/// and stopping one that feeds the replay buffer drops the buffer.
+fn spawn_stream(
+    window: tauri::Window,
+    info: StreamInfo,
+    handle: StreamHandle,
+    target: FrameTarget,
+) {
+    let queue = Arc::new(FrameQueue::<DisplayFrame>::new(DISPLAY_QUEUE_LEN));
+    let encoder = (!matches!(target, FrameTarget::Hidden)).then(|| {
+        let window = window.clone();
+        let queue = queue.clone();
+        let meter = handle.meter.clone();
+        let id = info.id;
+        thread::spawn(move || {
+            let mut errors = RepeatFilter::default();
+            while let Some((image, quality, max_width)) = queue.pop() {
+                let started = std::time::Instant::now();
+                let buffer = match stream::encode_jpeg(image, quality, max_width) {
+                    Ok(buffer) => buffer,
+                    Err(e) => {
+                        meter.failed(&e);
+                        if errors.is_new(&e) {
+                            report_stream_error(window.app_handle(), id, &e);
+                        }
+                        continue;
+                    }
+                };
+                errors.clear();
+                meter.sent(buffer.len(), started.elapsed());
+                match &target {
+                    FrameTarget::Channel(channel) => {
+                        let _ = channel.send(InvokeResponseBody::Raw(buffer));
+                    }
+                    FrameTarget::Event => {
+                        let data = general_purpose::STANDARD.encode(&buffer);
+                        let _ = window.emit(
+                            "screen-frame",
+                            ScreenFrame {
+                                stream_id: id,
+                                data,
+                            },
+                        );
+                    }
+                    FrameTarget::Hidden => {}
+                }
             }
-        };
+        })
+    });
 
-        // Find monitor to record
-        let monitor = if let Some(id) = monitor_id {
-            monitors.into_iter().find(|m| m.id().unwrap_or(0) == id)
-        } else {
-            monitors
-                .into_iter()
-                .find(|m| m.is_primary().unwrap_or(false))
-        };
+    let replay_queue = Arc::new(FrameQueue::new(REPLAY_QUEUE_LEN));
+    let replay_worker = (info.purpose == StreamPurpose::Replay).then(|| {
+        let app = window.app_handle().clone();
+        let queue = replay_queue.clone();
+        let handle = handle.clone();
+        let id = info.id;
+        thread::spawn(move || {
+            let state = app.state::<AppState>();
+            let mut errors = RepeatFilter::default();
+            while let Some((image, taken_at)) = queue.pop() {
+                // Only the frame rate check and the insert hold the lock
+                let options = match state.replay.lock().unwrap().as_ref() {
+                    Some((stream, buffer)) if *stream == id => {
+                        buffer.due(taken_at).then(|| buffer.options.clone())
+                    }
+                    _ => {
+                        handle.running.store(false, Ordering::SeqCst);
+                        break;
+                    }
+                };
+                let Some(options) = options else {
+                    continue;
+                };
+                match StoredFrame::compress(&image, taken_at, &options) {
+                    Ok(frame) => {
+                        errors.clear();
+                        if let Some((stream, buffer)) = state.replay.lock().unwrap().as_mut() {
+                            if *stream == id {
+                                buffer.push(frame);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        handle.meter.failed(&e);
+                        if errors.is_new(&e) {
+                            report_stream_error(&app, id, &e);
+                        }
+                    }
+                }
+            }
+        })
+    });
+
+    thread::spawn(move || {
+        let app = window.app_handle().clone();
+        let state = app.state::<AppState>();
+        let backend = state.backend.clone();
+        let StreamHandle { running, meter } = handle;
+        let id = info.id;
 
-        if let Some(target_monitor) = monitor {
-            println!(
-                "Starting capture on monitor: {}",
-                target_monitor.name().unwrap_or_default()
-            );
-            while is_streaming_clone.load(Ordering::SeqCst) {
-                let start = std::time::Instant::now();
-                match target_monitor.capture_image() {
-                    Ok(image) => {
-                        let dynamic_image = DynamicImage::ImageRgba8(image);
-                        let rgb_image = dynamic_image.to_rgb8();
-                        let width = rgb_image.width();
-                        let height = rgb_image.height();
-                        let mut buffer = Vec::new();
-                        let mut cursor = Cursor::new(&mut buffer);
-
-                        // Use JPEG with high quality (90) and RGB8 to avoid gray screen and artifacts
-                        let mut encoder =
-                            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut cursor, 90);
-                        match encoder.encode(
-                            &rgb_image,
-                            width,
-                            height,
-                            image::ExtendedColorType::Rgb8,
-                        ) {
-                            Ok(_) => {
-                                let encoded = general_purpose::STANDARD.encode(&buffer);
-                                let _ = window.emit("screen-frame", encoded);
+        let _ = app.emit("stream-started", &info);
+        let mut last_preview: Option<std::time::Instant> = None;
+        let interval = Duration::from_secs(1) / info.fps;
+        stream::capture_loop(
+            &running,
+            interval,
+            &meter,
+            || info.source.capture(backend.as_ref()),
+            |image, taken_at| {
+                let frame = match info.purpose {
+                    StreamPurpose::View => (image, 90, None),
+                    StreamPurpose::Replay => {
+                        if replay_queue.push((image, taken_at)) {
+                            meter.dropped();
+                        }
+                        return;
+                    }
+                    StreamPurpose::Recording => {
+                        let mut recording = state.recording.lock().unwrap();
+                        if recording.stream() != Some(id) {
+                            running.store(false, Ordering::SeqCst);
+                            return;
+                        }
+                        let Some(recorder) = recording.recorder() else {
+                            return;
+                        };
+                        let due = last_preview.is_none_or(|last| {
+                            taken_at.duration_since(last) >= recorder.options.preview_interval()
+                        });
+                        let preview = due.then(|| image.clone());
+                        recorder.push(image, taken_at);
+                        if let Some(reason) = recorder.limit_reached() {
+                            if let Ok(recorder) = recording.stop() {
+                                stop_in_background(app.clone(), recorder, reason);
                             }
-                            Err(e) => println!("Encoding error: {}", e),
                         }
+                        let Some(image) = preview else {
+                            return;
+                        };
+                        (image, 70, Some(PREVIEW_WIDTH))
                     }
-                    Err(e) => println!("Capture error: {}", e),
+                };
+                last_preview = Some(taken_at);
+                if queue.push(frame) {
+                    meter.dropped();
                 }
+            },
+            |e| report_stream_error(&app, id, e),
+        );
 
-                // Cap at ~30 FPS (33ms)
-                let elapsed = start.elapsed();
-                if elapsed < Duration::from_millis(33) {
-                    thread::sleep(Duration::from_millis(33) - elapsed);
+        queue.close();
+        replay_queue.close();
+        if let Some(encoder) = encoder {
+            let _ = encoder.join();
+        }
+        if let Some(worker) = replay_worker {
+            let _ = worker.join();
+        }
+        state.streams.lock().unwrap().remove(id);
+        match info.purpose {
+            StreamPurpose::View => {}
+            StreamPurpose::Replay => {
+                let mut replay = state.replay.lock().unwrap();
+                if replay.as_ref().is_some_and(|(stream, _)| *stream == id) {
+                    replay.take();
+                }
+            }
+            StreamPurpose::Recording => {
+                let mut recording = state.recording.lock().unwrap();
+                if recording.stream() == Some(id) {
+                    if let Ok(recorder) = recording.stop() {
+                        stop_in_background(app.clone(), recorder, StopReason::Requested);
+                    }
                 }
             }
-        } else {
-            println!("Monitor not found!");
         }
+        let _ = app.emit("stream-stopped", StreamStopped { stream_id: id });
     });
+}
 
+/// Stops every stream started with `start_streaming`. Recording and replay
+/// streams keep running.
+#[tauri::command]
+fn stop_streaming(state: State<'_, AppState>) -> Result<(), CaptureError> {
+    state.streams.lock().unwrap().stop_all(StreamPurpose::View);
     Ok(())
 }
 
+/// Stops one stream after its current frame. A `stream-stopped` event
+/// follows once it has.
 #[tauri::command]
-fn stop_streaming(state: State<'_, AppState>) -> Result<(), String> {
-    state.is_streaming.store(false, Ordering::SeqCst);
-    Ok(())
+fn stop_stream(state: State<'_, AppState>, id: u64) -> Result<(), CaptureError> {
+    state.streams.lock().unwrap().stop(id)
+}
+
+#[tauri::command]
+fn list_streams(state: State<'_, AppState>) -> Vec<StreamInfo> {
+    state.streams.lock().unwrap().list()
 }
 
-fn get_monitor_at_cursor() -> Option<Monitor> {
-    let position = Mouse::get_mouse_position();
-    let (mouse_x, mouse_y) = match position {
-        Mouse::Position { x, y } => (x, y),
-        _ => return None,
+#[tauri::command]
+fn get_stream_stats(state: State<'_, AppState>, id: u64) -> Result<StreamStats, CaptureError> {
+    state.streams.lock().unwrap().stats(id)
+}
+
+/// Starts recording `source`, or `monitor_id` if no source is given, to AV1
+/// WebM or GIF in a temporary file and returns its session ID for
+/// `finalize_video_file`. Frames come from a stream of their own, which
+/// sends previews to `on_preview`.
+#[tauri::command]
+fn start_recording(
+    window: tauri::Window,
+    webview: tauri::Webview,
+    state: State<'_, AppState>,
+    monitor_id: Option<u32>,
+    source: Option<CaptureSource>,
+    options: Option<RecordingOptions>,
+    on_preview: Option<JavaScriptChannelId>,
+) -> Result<u64, CaptureError> {
+    let mut recording = state.recording.lock().unwrap();
+    recording.expect(RecordingState::Idle)?;
+
+    let options = options.unwrap_or_default();
+    options.validate()?;
+    let source = source.unwrap_or(CaptureSource::Monitor { monitor_id });
+    let (info, handle) = add_stream(&state, source, options.fps, StreamPurpose::Recording)?;
+    let app = window.app_handle().clone();
+    let session = match recordings_dir(&app).and_then(|dir| {
+        state
+            .video_files
+            .lock()
+            .unwrap()
+            .open(&dir, options.format.extension())
+    }) {
+        Ok(session) => session,
+        Err(e) => {
+            state.streams.lock().unwrap().remove(info.id);
+            return Err(e);
+        }
+    };
+    let sink_app = app.clone();
+    let sink = move |data: &[u8]| {
+        if !data.is_empty() {
+            let state = sink_app.state::<AppState>();
+            state.video_files.lock().unwrap().append(session, data)?;
+        }
+        Ok(())
     };
+    match Recorder::start(session, options, sink).and_then(|r| recording.start(r, info.id)) {
+        Ok(()) => {}
+        Err(e) => {
+            let _ = state.video_files.lock().unwrap().abort(session);
+            state.streams.lock().unwrap().remove(info.id);
+            return Err(e);
+        }
+    }
+    drop(recording);
+    set_recording_state(&app, RecordingState::Recording, None);
 
-    let monitors = Monitor::all().ok()?;
+    let target = match on_preview {
+        Some(channel) => FrameTarget::Channel(channel.channel_on(webview)),
+        None => FrameTarget::Event,
+    };
+    spawn_stream(window, info, handle, target);
+    Ok(session)
+}
 
-    for m in monitors {
-        let x = m.x().unwrap_or(0);
-        let y = m.y().unwrap_or(0);
-        let width = m.width().unwrap_or(0) as i32;
-        let height = m.height().unwrap_or(0) as i32;
+#[derive(Clone, serde::Serialize)]
+struct RecordingResult {
+    session: u64,
+    extension: &'static str,
+    #[serde(flatten)]
+    stats: RecordingStats,
+}
 
-        if mouse_x >= x && mouse_x < x + width && mouse_y >= y && mouse_y < y + height {
-            return Some(m);
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordingStateEvent {
+    state: RecordingState,
+    /// Why the recording stopped, once it is finalizing or idle again.
+    reason: Option<StopReason>,
+}
+
+const TRAY_ID: &str = "main";
+
+/// Tray menu entries that follow the recording state.
+struct TrayRecordingItems {
+    pause: MenuItem<tauri::Wry>,
+    stop: MenuItem<tauri::Wry>,
+}
+
+/// Tells the webview and the tray that the recording moved to `state`.
+fn set_recording_state(app: &tauri::AppHandle, state: RecordingState, reason: Option<StopReason>) {
+    let _ = app.emit("recording-state", RecordingStateEvent { state, reason });
+
+    let tooltip = match state {
+        RecordingState::Idle => None,
+        RecordingState::Recording => Some("Recording"),
+        RecordingState::Paused => Some("Recording paused"),
+        RecordingState::Finalizing => Some("Saving recording"),
+    };
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        let _ = tray.set_tooltip(tooltip);
+    }
+    if let Some(items) = app.try_state::<TrayRecordingItems>() {
+        let active = matches!(state, RecordingState::Recording | RecordingState::Paused);
+        let pause_text = match state {
+            RecordingState::Paused => "Resume Recording",
+            _ => "Pause Recording",
+        };
+        let _ = items.pause.set_text(pause_text);
+        let _ = items.pause.set_enabled(active);
+        let _ = items.stop.set_enabled(active);
+    }
+}
+
+/// Waits for a stopped recorder to write its remaining frames and returns
+/// the session to finalize. The file is deleted if encoding failed.
+fn finish_recording(
+    app: &tauri::AppHandle,
+    recorder: Recorder,
+    reason: StopReason,
+) -> Result<RecordingResult, CaptureError> {
+    let state = app.state::<AppState>();
+    set_recording_state(app, RecordingState::Finalizing, Some(reason));
+
+    let session = recorder.session;
+    let extension = recorder.options.format.extension();
+    let finished = recorder.finish();
+    state.recording.lock().unwrap().finalized();
+    set_recording_state(app, RecordingState::Idle, Some(reason));
+    match finished {
+        Ok(stats) => Ok(RecordingResult {
+            session,
+            extension,
+            stats,
+        }),
+        Err(e) => {
+            let _ = state.video_files.lock().unwrap().abort(session);
+            Err(e)
         }
     }
-    None
-}
-
-fn capture_monitor_at_cursor(app_handle: tauri::AppHandle) {
-    if let Some(monitor) = get_monitor_at_cursor() {
-        match monitor.capture_image() {
-            Ok(image) => {
-                let dynamic_image = DynamicImage::ImageRgba8(image);
-                let mut buffer = Vec::new();
-                let mut cursor = Cursor::new(&mut buffer);
-                if let Ok(_) = dynamic_image.write_to(&mut cursor, ImageFormat::Png) {
-                    let encoded = general_purpose::STANDARD.encode(&buffer);
-                    let _ = app_handle.emit("start-area-capture", encoded);
-
-                    if let Some(window) = app_handle.get_webview_window("main") {
-                        let _ = window.show();
-                        let _ = window.set_focus();
+}
+
+#[derive(Clone, serde::Serialize)]
+struct RecordingStopped {
+    reason: StopReason,
+    #[serde(flatten)]
+    result: RecordingResult,
+}
+
+/// Finishes a recording stopped by something other than `stop_recording`,
+/// such as a limit or the tray, and hands the result to the webview in a
+/// `recording-stopped` event so it can be saved.
+fn stop_in_background(app: tauri::AppHandle, recorder: Recorder, reason: StopReason) {
+    thread::spawn(move || match finish_recording(&app, recorder, reason) {
+        Ok(result) => {
+            let _ = app.emit("recording-stopped", RecordingStopped { reason, result });
+        }
+        Err(e) => report_background_error(&app, "Stopping the recording failed", &e),
+    });
+}
+
+/// Stops the recording, waits for the encoder to write the remaining frames
+/// and returns the session to finalize.
+#[tauri::command]
+async fn stop_recording(app: tauri::AppHandle) -> Result<RecordingResult, CaptureError> {
+    let recorder = app.state::<AppState>().recording.lock().unwrap().stop()?;
+    finish_recording(&app, recorder, StopReason::Requested)
+}
+
+#[tauri::command]
+fn pause_recording(app: tauri::AppHandle, state: State<'_, AppState>) -> Result<(), CaptureError> {
+    state.recording.lock().unwrap().pause()?;
+    set_recording_state(&app, RecordingState::Paused, None);
+    Ok(())
+}
+
+#[tauri::command]
+fn resume_recording(app: tauri::AppHandle, state: State<'_, AppState>) -> Result<(), CaptureError> {
+    state.recording.lock().unwrap().resume()?;
+    set_recording_state(&app, RecordingState::Recording, None);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_recording_state(state: State<'_, AppState>) -> RecordingState {
+    state.recording.lock().unwrap().state()
+}
+
+/// Starts keeping the last few seconds of `source`, or `monitor_id` if no
+/// source is given, in memory so they can be saved with `save_replay`. The
+/// frames come from a stream of their own, without display output. A
+/// running replay buffer is replaced.
+#[tauri::command]
+fn start_replay(
+    window: tauri::Window,
+    state: State<'_, AppState>,
+    monitor_id: Option<u32>,
+    source: Option<CaptureSource>,
+    options: Option<ReplayOptions>,
+) -> Result<(), CaptureError> {
+    let buffer = ReplayBuffer::new(options.unwrap_or_default())?;
+    let source = source.unwrap_or(CaptureSource::Monitor { monitor_id });
+    let (info, handle) = add_stream(&state, source, buffer.options.fps, StreamPurpose::Replay)?;
+    *state.replay.lock().unwrap() = Some((info.id, buffer));
+    spawn_stream(window, info, handle, FrameTarget::Hidden);
+    Ok(())
+}
+
+/// Drops the replay buffer and its frames. Its stream stops by itself.
+#[tauri::command]
+fn stop_replay(state: State<'_, AppState>) {
+    state.replay.lock().unwrap().take();
+}
+
+#[tauri::command]
+fn get_replay_status(state: State<'_, AppState>) -> Option<ReplayStatus> {
+    state
+        .replay
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|(_, buffer)| buffer.status())
+}
+
+/// Encodes what the replay buffer holds to a temporary file. The buffer
+/// keeps running.
+fn encode_replay(
+    app: &tauri::AppHandle,
+    options: RecordingOptions,
+) -> Result<RecordingResult, CaptureError> {
+    options.validate()?;
+    let state = app.state::<AppState>();
+    let frames = state
+        .replay
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|(_, buffer)| buffer.snapshot())
+        .ok_or_else(|| CaptureError::invalid("The replay buffer is not running"))?;
+    if frames.is_empty() {
+        return Err(CaptureError::invalid("The replay buffer is empty"));
+    }
+
+    let extension = options.format.extension();
+    let session = state
+        .video_files
+        .lock()
+        .unwrap()
+        .open(&recordings_dir(app)?, extension)?;
+    let encoded = recording::encode_frames(
+        frames.iter().map(|f| Ok((f.decode()?, f.taken_at))),
+        &options,
+        |data| {
+            state.video_files.lock().unwrap().append(session, data)?;
+            Ok(())
+        },
+    );
+    match encoded {
+        Ok(stats) => Ok(RecordingResult {
+            session,
+            extension,
+            stats,
+        }),
+        Err(e) => {
+            let _ = state.video_files.lock().unwrap().abort(session);
+            Err(e)
+        }
+    }
+}
+
+/// Saves the replay buffer to a temporary file and returns the session to
+/// finalize, like `stop_recording`.
+#[tauri::command]
+async fn save_replay(
+    app: tauri::AppHandle,
+    options: Option<RecordingOptions>,
+) -> Result<RecordingResult, CaptureError> {
+    encode_replay(&app, options.unwrap_or_default())
+}
+
+fn cursor_position() -> Option<(i32, i32)> {
+    match Mouse::get_mouse_position() {
+        Mouse::Position { x, y } => Some((x, y)),
+        _ => None,
+    }
+}
+
+fn get_monitor_at_cursor(backend: &dyn CaptureBackend) -> Option<u32> {
+    let (mouse_x, mouse_y) = cursor_position()?;
+    backend.monitor_at_point(mouse_x, mouse_y).ok()?
+}
+
+fn get_window_at_cursor(backend: &dyn CaptureBackend) -> Result<WindowInfo, CaptureError> {
+    let (mouse_x, mouse_y) = cursor_position()
+        .ok_or_else(|| CaptureError::NoMatchingWindow("Cursor position unavailable".to_string()))?;
+    backend::window_at_point(backend, mouse_x, mouse_y)
+}
+
+/// Shortcut capture: stores what `source` picks, emits `event` with the
+/// capture's metadata and brings the main window forward. With `auto_save`,
+/// the capture is also written to disk if the settings ask for it.
+fn capture_for_shortcut(
+    app_handle: &tauri::AppHandle,
+    event: &str,
+    auto_save: bool,
+    source: impl FnOnce(&AppState) -> Result<CaptureSource, CaptureError>,
+) {
+    let state = app_handle.state::<AppState>();
+    let result = source(&state).and_then(|source| {
+        let image = source.capture(state.backend.as_ref())?;
+        Ok(store_capture(app_handle, image, source, None))
+    });
+    match result {
+        Ok(meta) => {
+            if auto_save {
+                match auto_save_capture(app_handle, &meta) {
+                    Ok(Some(path)) => {
+                        let _ = app_handle.emit("capture-saved", path);
                     }
+                    Ok(None) => {}
+                    Err(e) => report_background_error(app_handle, "Auto-save failed", &e),
                 }
             }
-            Err(e) => println!("Failed to capture monitor: {}", e),
+            let _ = app_handle.emit(event, meta);
+            if let Some(window) = app_handle.get_webview_window("main") {
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
         }
+        Err(e) => report_background_error(app_handle, "Shortcut capture failed", &e),
+    }
+}
+
+/// A failure in work the webview did not start itself, such as a shortcut
+/// capture, sent in a `background-error` event.
+#[derive(Clone, serde::Serialize)]
+struct BackgroundError {
+    context: &'static str,
+    code: &'static str,
+    message: String,
+}
+
+fn report_background_error(app: &tauri::AppHandle, context: &'static str, error: &CaptureError) {
+    let _ = app.emit(
+        "background-error",
+        BackgroundError {
+            context,
+            code: error.code(),
+            message: error.to_string(),
+        },
+    );
+}
+
+/// Writes a stored capture to the default folder when auto-save is enabled,
+/// returning where it went.
+fn auto_save_capture(
+    app_handle: &tauri::AppHandle,
+    meta: &CaptureMeta,
+) -> Result<Option<PathBuf>, CaptureError> {
+    let settings = app_handle.state::<SettingsStore>().get();
+    let (true, Some(folder)) = (settings.auto_save, settings.default_path) else {
+        return Ok(None);
+    };
+    let mode = match meta.source {
+        CaptureSource::Window { .. } | CaptureSource::FollowWindow { .. } => "window",
+        CaptureSource::Region { .. } => "area",
+        _ => "fullscreen",
+    };
+    let state = app_handle.state::<AppState>();
+    let fields = name_fields(
+        state.backend.as_ref(),
+        mode.to_string(),
+        Some(&meta.source),
+        Some((meta.width, meta.height)),
+    );
+    let path = naming::next_path(
+        Some(Path::new(&folder)),
+        &settings.image_template,
+        &fields,
+        "png",
+        settings.on_collision,
+    )?;
+    let (_, image) = state.captures.lock().unwrap().get(meta.id)?;
+    let overwrite = settings.on_collision == Collision::Overwrite;
+    files::write_atomic(&path, &encode_png(&image)?, overwrite)?;
+    Ok(Some(path))
+}
+
+fn monitor_source_at_cursor(state: &AppState) -> Result<CaptureSource, CaptureError> {
+    let monitor_id = get_monitor_at_cursor(state.backend.as_ref())
+        .ok_or(CaptureError::MonitorNotFound { id: None })?;
+    Ok(CaptureSource::Monitor {
+        monitor_id: Some(monitor_id),
+    })
+}
+
+fn run_shortcut_action(app_handle: tauri::AppHandle, action: ShortcutAction) {
+    match action {
+        ShortcutAction::AreaCapture => capture_for_shortcut(
+            &app_handle,
+            "start-area-capture",
+            false,
+            monitor_source_at_cursor,
+        ),
+        ShortcutAction::Fullscreen => capture_for_shortcut(
+            &app_handle,
+            "screen-captured",
+            true,
+            monitor_source_at_cursor,
+        ),
+        ShortcutAction::ActiveWindow => {
+            capture_for_shortcut(&app_handle, "window-captured", true, |state| {
+                let window = backend::active_window(state.backend.as_ref())?;
+                Ok(CaptureSource::Window { id: window.id })
+            })
+        }
+        ShortcutAction::WindowAtCursor => {
+            capture_for_shortcut(&app_handle, "window-captured", true, |state| {
+                let window = get_window_at_cursor(state.backend.as_ref())?;
+                Ok(CaptureSource::Window { id: window.id })
+            })
+        }
+        ShortcutAction::RepeatLastRegion => {
+            capture_for_shortcut(&app_handle, "screen-captured", true, |state| {
+                state
+                    .last_region
+                    .lock()
+                    .unwrap()
+                    .clone()
+                    .ok_or_else(|| CaptureError::invalid("No region captured yet"))
+            })
+        }
+        // The webview drives recording and its save flow, so just tell it
+        // to toggle
+        ShortcutAction::ToggleRecording => {
+            let _ = app_handle.emit("toggle-recording", ());
+        }
+        // Encoding happens here; the webview picks where the file goes
+        ShortcutAction::SaveReplay => match encode_replay(&app_handle, RecordingOptions::default())
+        {
+            Ok(result) => {
+                let _ = app_handle.emit("replay-ready", &result);
+            }
+            Err(e) => report_background_error(&app_handle, "Saving the replay failed", &e),
+        },
+    }
+}
+
+#[tauri::command]
+fn get_settings(settings: State<'_, SettingsStore>) -> Settings {
+    settings.get()
+}
+
+/// Changes the settings named in `patch`, saves them and broadcasts
+/// `settings-changed` so every window picks up the new values. The default
+/// folder widens where files may be written, so it can only be changed
+/// through `choose_default_folder`.
+#[tauri::command]
+fn update_settings(
+    app: tauri::AppHandle,
+    settings: State<'_, SettingsStore>,
+    patch: serde_json::Map<String, serde_json::Value>,
+) -> Result<Settings, CaptureError> {
+    if patch.contains_key("defaultPath") {
+        return Err(CaptureError::invalid(
+            "defaultPath can only be changed with choose_default_folder",
+        ));
     }
+    let updated = settings.update(patch)?;
+    let _ = app.emit("settings-changed", &updated);
+    Ok(updated)
+}
+
+/// Moves settings the webview kept in localStorage, passed as the raw
+/// string values, into the settings file. The default folder is dropped;
+/// it has to be picked again with `choose_default_folder`.
+#[tauri::command]
+fn import_legacy_settings(
+    app: tauri::AppHandle,
+    settings: State<'_, SettingsStore>,
+    values: serde_json::Map<String, serde_json::Value>,
+) -> Result<Settings, CaptureError> {
+    let updated = settings.import_legacy(values)?;
+    let _ = app.emit("settings-changed", &updated);
+    Ok(updated)
+}
+
+#[tauri::command]
+fn get_keymap(keymap: State<'_, KeymapState>) -> KeymapStatus {
+    keymap.status()
+}
+
+/// Saves `keymap` and re-registers every global shortcut from it. Bindings
+/// that could not be registered are returned in `failures`; the rest stay
+/// active.
+#[tauri::command]
+fn set_keymap(app: tauri::AppHandle, keymap: Keymap) -> Result<KeymapStatus, CaptureError> {
+    shortcuts::save(&app, &keymap)?;
+    shortcuts::apply(&app, keymap);
+    Ok(app.state::<KeymapState>().status())
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -289,24 +1601,13 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(
             tauri_plugin_global_shortcut::Builder::new()
-                .with_shortcuts(vec![
-                    Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::F11),
-                    Shortcut::new(Some(Modifiers::SUPER | Modifiers::SHIFT), Code::F11),
-                ])
-                .unwrap()
                 .with_handler(|app, shortcut, event| {
-                    if event.state == ShortcutState::Pressed {
-                        let ctrl_shift_f11 =
-                            Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::F11);
-                        let cmd_shift_f11 =
-                            Shortcut::new(Some(Modifiers::SUPER | Modifiers::SHIFT), Code::F11);
-
-                        if shortcut == &ctrl_shift_f11 || shortcut == &cmd_shift_f11 {
-                            let app_handle = app.clone();
-                            thread::spawn(move || {
-                                capture_monitor_at_cursor(app_handle);
-                            });
-                        }
+                    if event.state != ShortcutState::Pressed {
+                        return;
+                    }
+                    if let Some(action) = app.state::<KeymapState>().action_for(shortcut) {
+                        let app_handle = app.clone();
+                        thread::spawn(move || run_shortcut_action(app_handle, action));
                     }
                 })
                 .build(),
@@ -315,11 +1616,31 @@ pub fn run() {
             #[cfg(target_os = "macos")]
             app.set_activation_policy(tauri::ActivationPolicy::Accessory);
 
+            app.manage(SettingsStore::load(app.handle()));
+
+            let (keymap, load_error) = shortcuts::load(app.handle());
+            let failures = shortcuts::apply(app.handle(), keymap);
+            if let Some(error) = load_error {
+                app.state::<KeymapState>().report(error);
+            }
+            for failure in &failures {
+                println!(
+                    "Could not register {} for {:?}: {}",
+                    failure.accelerator, failure.action, failure.reason
+                );
+            }
+
             let quit_i = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
             let show_i = MenuItem::with_id(app, "show", "Show", true, None::<&str>)?;
-            let menu = Menu::with_items(app, &[&show_i, &quit_i])?;
+            let pause_i = MenuItem::with_id(app, "pause", "Pause Recording", false, None::<&str>)?;
+            let stop_i = MenuItem::with_id(app, "stop", "Stop Recording", false, None::<&str>)?;
+            let menu = Menu::with_items(app, &[&show_i, &pause_i, &stop_i, &quit_i])?;
+            app.manage(TrayRecordingItems {
+                pause: pause_i,
+                stop: stop_i,
+            });
 
-            let _tray = TrayIconBuilder::new()
+            let _tray = TrayIconBuilder::with_id(TRAY_ID)
                 .icon(app.default_window_icon().unwrap().clone())
                 .menu(&menu)
                 .show_menu_on_left_click(false)
@@ -333,6 +1654,25 @@ pub fn run() {
                             let _ = window.set_focus();
                         }
                     }
+                    "pause" => {
+                        let state = app.state::<AppState>();
+                        let mut recording = state.recording.lock().unwrap();
+                        let next = match recording.state() {
+                            RecordingState::Paused => recording.resume(),
+                            _ => recording.pause(),
+                        };
+                        let new_state = recording.state();
+                        drop(recording);
+                        if next.is_ok() {
+                            set_recording_state(app, new_state, None);
+                        }
+                    }
+                    "stop" => {
+                        let stopped = app.state::<AppState>().recording.lock().unwrap().stop();
+                        if let Ok(recorder) = stopped {
+                            stop_in_background(app.clone(), recorder, StopReason::Requested);
+                        }
+                    }
                     _ => {}
                 })
                 .on_tray_icon_event(|tray, event| {
@@ -351,29 +1691,237 @@ pub fn run() {
                 .build(app)?;
             Ok(())
         })
-        .on_window_event(|window, event| match event {
-            tauri::WindowEvent::CloseRequested { api, .. } => {
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
                 println!("Window close requested, hiding window...");
                 if let Err(e) = window.hide() {
                     println!("Error hiding window: {}", e);
                 }
                 api.prevent_close();
             }
-            _ => {}
         })
-        .manage(AppState {
-            is_streaming: Arc::new(AtomicBool::new(false)),
+        .register_asynchronous_uri_scheme_protocol("capture", |ctx, request, responder| {
+            let app = ctx.app_handle().clone();
+            thread::spawn(move || responder.respond(serve_capture(&app, &request)));
         })
+        .manage(AppState::new(backend::from_env()))
+        .manage(KeymapState::default())
         .invoke_handler(tauri::generate_handler![
             capture_screen,
+            capture_region,
+            capture_desktop_region,
+            capture_all_monitors,
             get_monitors,
             get_windows,
+            get_window_thumbnails,
             capture_window,
+            capture_active_window,
+            capture_window_at_cursor,
+            capture_window_matching,
             save_image,
+            take_capture,
+            list_captures,
+            crop_capture,
+            encode_capture,
+            save_capture,
+            export_image,
+            import_capture,
+            copy_capture,
+            discard_capture,
             save_video,
+            open_video_file,
+            append_video_chunk,
+            finalize_video_file,
+            abort_video_file,
+            choose_save_path,
+            choose_default_folder,
             start_streaming,
-            stop_streaming
+            stop_streaming,
+            stop_stream,
+            list_streams,
+            get_stream_stats,
+            start_recording,
+            stop_recording,
+            pause_recording,
+            resume_recording,
+            get_recording_state,
+            start_replay,
+            stop_replay,
+            get_replay_status,
+            save_replay,
+            next_save_path,
+            get_settings,
+            update_settings,
+            import_legacy_settings,
+            get_keymap,
+            set_keymap
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::SyntheticBackend;
+    use serde_json::{json, Value};
+    use tauri::ipc::CallbackFn;
+    use tauri::test::{mock_builder, mock_context, noop_assets, MockRuntime, INVOKE_KEY};
+    use tauri::webview::InvokeRequest;
+    use tauri::WebviewWindow;
+
+    /// The capture commands on the synthetic backend, saving into `folder`.
+    fn app(folder: &Path) -> (tauri::App<MockRuntime>, WebviewWindow<MockRuntime>) {
+        let settings = Settings {
+            default_path: Some(folder.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let app = mock_builder()
+            .manage(AppState::new(Arc::new(SyntheticBackend::default())))
+            .manage(SettingsStore::in_memory(settings))
+            .invoke_handler(tauri::generate_handler![
+                capture_screen,
+                take_capture,
+                list_captures,
+                crop_capture,
+                encode_capture,
+                save_capture,
+                next_save_path,
+                export_image,
+                discard_capture,
+            ])
+            .build(mock_context(noop_assets()))
+            .unwrap();
+        let webview = tauri::WebviewWindowBuilder::new(&app, "main", Default::default())
+            .build()
+            .unwrap();
+        (app, webview)
+    }
+
+    fn invoke_body(
+        webview: &WebviewWindow<MockRuntime>,
+        cmd: &str,
+        args: Value,
+    ) -> Result<InvokeResponseBody, Value> {
+        tauri::test::get_ipc_response(
+            webview,
+            InvokeRequest {
+                cmd: cmd.into(),
+                callback: CallbackFn(0),
+                error: CallbackFn(1),
+                url: "http://tauri.localhost".parse().unwrap(),
+                body: InvokeBody::Json(args),
+                headers: Default::default(),
+                invoke_key: INVOKE_KEY.to_string(),
+            },
+        )
+    }
+
+    fn invoke(
+        webview: &WebviewWindow<MockRuntime>,
+        cmd: &str,
+        args: Value,
+    ) -> Result<Value, Value> {
+        invoke_body(webview, cmd, args).map(|body| body.deserialize().unwrap())
+    }
+
+    fn decode(body: InvokeResponseBody) -> RgbaImage {
+        let InvokeResponseBody::Raw(bytes) = body else {
+            panic!("expected raw bytes");
+        };
+        image::load_from_memory(&bytes).unwrap().to_rgba8()
+    }
+
+    #[test]
+    fn capture_screen_returns_png() {
+        let folder = tempfile::tempdir().unwrap();
+        let (_app, webview) = app(folder.path());
+        let image =
+            decode(invoke_body(&webview, "capture_screen", json!({ "monitorId": 2 })).unwrap());
+        assert_eq!(image, SyntheticBackend::test_pattern(1280, 1024, 2));
+
+        let error = invoke(&webview, "capture_screen", json!({ "monitorId": 9 })).unwrap_err();
+        assert_eq!(error["code"], "monitor_not_found");
+    }
+
+    #[test]
+    fn capture_crop_and_export() {
+        let folder = tempfile::tempdir().unwrap();
+        let (_app, webview) = app(folder.path());
+        let source = json!({ "kind": "monitor", "monitorId": 1 });
+        let capture = invoke(&webview, "take_capture", json!({ "source": source })).unwrap();
+        assert_eq!(
+            (capture["width"].clone(), capture["height"].clone()),
+            (json!(1920), json!(1080))
+        );
+
+        let args = json!({ "id": capture["id"], "x": 10, "y": 20, "width": 300, "height": 200 });
+        let cropped = invoke(&webview, "crop_capture", args).unwrap();
+        assert_eq!(cropped["parent"], capture["id"]);
+        let listed = invoke(&webview, "list_captures", json!({})).unwrap();
+        assert_eq!(listed.as_array().unwrap().len(), 2);
+
+        let args = json!({ "mode": "region", "extension": "png", "captureId": cropped["id"] });
+        let path = invoke(&webview, "next_save_path", args).unwrap();
+        let path = PathBuf::from(path.as_str().unwrap());
+        assert!(path.starts_with(folder.path()));
+
+        let args = json!({ "id": cropped["id"], "path": path });
+        let exported = invoke(&webview, "export_image", args).unwrap();
+        assert_eq!(exported["format"], "png");
+        let saved = image::open(&path).unwrap().to_rgba8();
+        let expected = SyntheticBackend::test_pattern(1920, 1080, 1);
+        assert_eq!(saved.dimensions(), (300, 200));
+        assert_eq!(saved.get_pixel(0, 0), expected.get_pixel(10, 20));
+
+        let jpeg = folder.path().join("shot.jpg");
+        let args = json!({ "id": cropped["id"], "path": jpeg });
+        let exported = invoke(&webview, "export_image", args).unwrap();
+        assert_eq!(exported["format"], "jpeg");
+        assert_eq!(image::open(&jpeg).unwrap().width(), 300);
+    }
+
+    #[test]
+    fn saves_only_inside_the_folder() {
+        let folder = tempfile::tempdir().unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        let (_app, webview) = app(folder.path());
+        let source = json!({ "kind": "window", "id": 101 });
+        let capture = invoke(&webview, "take_capture", json!({ "source": source })).unwrap();
+        assert_eq!(capture["width"], 800);
+
+        let inside = folder.path().join("window.png");
+        let args = json!({ "id": capture["id"], "path": inside });
+        invoke(&webview, "save_capture", args).unwrap();
+        assert_eq!(image::open(&inside).unwrap().width(), 800);
+
+        let args = json!({ "id": capture["id"], "path": inside, "overwrite": false });
+        let error = invoke(&webview, "save_capture", args).unwrap_err();
+        assert_eq!(error["code"], "file_exists");
+
+        let outside = elsewhere.path().join("window.png");
+        let args = json!({ "id": capture["id"], "path": outside });
+        let error = invoke(&webview, "save_capture", args).unwrap_err();
+        assert_eq!(error["code"], "path_not_allowed");
+        assert!(!outside.exists());
+    }
+
+    #[test]
+    fn discarded_captures_are_gone() {
+        let folder = tempfile::tempdir().unwrap();
+        let (_app, webview) = app(folder.path());
+        let source = json!({ "kind": "allMonitors" });
+        let capture = invoke(&webview, "take_capture", json!({ "source": source })).unwrap();
+        let image = decode(
+            invoke_body(&webview, "encode_capture", json!({ "id": capture["id"] })).unwrap(),
+        );
+        assert_eq!(image.dimensions(), (3200, 1080));
+
+        invoke(&webview, "discard_capture", json!({ "id": capture["id"] })).unwrap();
+        let error = invoke(&webview, "encode_capture", json!({ "id": capture["id"] })).unwrap_err();
+        assert_eq!(error["code"], "capture_not_found");
+        let missing = json!({ "kind": "window", "id": 999 });
+        let error = invoke(&webview, "take_capture", json!({ "source": missing })).unwrap_err();
+        assert_eq!(error["code"], "window_not_found");
+    }
+}
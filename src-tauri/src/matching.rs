@@ -0,0 +1,203 @@
+use crate::backend::WindowInfo;
+use crate::error::{CaptureError, WindowCandidate};
+use regex::Regex;
+
+/// A text pattern matched against a window's title or app name.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "type", content = "pattern", rename_all = "lowercase")]
+pub enum Pattern {
+    /// Case-insensitive exact match.
+    Exact(String),
+    /// Case-insensitive glob with `*` and `?`.
+    Glob(String),
+    /// Regular expression, searched anywhere in the text. Use `(?i)` for
+    /// case-insensitive matching.
+    Regex(String),
+}
+
+enum Matcher {
+    Exact(String),
+    Glob(Vec<char>),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(pattern: &Pattern) -> Result<Self, CaptureError> {
+        Ok(match pattern {
+            Pattern::Exact(text) => Matcher::Exact(text.to_lowercase()),
+            Pattern::Glob(glob) => Matcher::Glob(glob.to_lowercase().chars().collect()),
+            Pattern::Regex(re) => {
+                Matcher::Regex(Regex::new(re).map_err(|e| CaptureError::invalid(e.to_string()))?)
+            }
+        })
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Matcher::Exact(expected) => text.to_lowercase() == *expected,
+            Matcher::Glob(glob) => {
+                let text: Vec<char> = text.to_lowercase().chars().collect();
+                glob_matches(glob, &text)
+            }
+            Matcher::Regex(re) => re.is_match(text),
+        }
+    }
+}
+
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    // Iterative wildcard matching with backtracking to the last `*`
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        // `*` comes first so it stays a wildcard even where the text has a
+        // literal `*`, as in the titles of unsaved documents
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// How to choose when several windows match.
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pick {
+    /// Fail with the list of candidates unless exactly one window matches.
+    #[default]
+    Unique,
+    /// The match closest to the front.
+    Frontmost,
+    /// The match with the largest area.
+    Largest,
+    /// Every match, frontmost first.
+    All,
+}
+
+/// Selects windows whose app name and title match the given patterns.
+/// Minimized windows are never selected since they capture black.
+pub fn select(
+    windows: Vec<WindowInfo>,
+    app_name: Option<&Pattern>,
+    title: Option<&Pattern>,
+    pick: Pick,
+) -> Result<Vec<WindowInfo>, CaptureError> {
+    if app_name.is_none() && title.is_none() {
+        return Err(CaptureError::invalid("Give an app name or title pattern"));
+    }
+    let app_matcher = app_name.map(Matcher::new).transpose()?;
+    let title_matcher = title.map(Matcher::new).transpose()?;
+
+    let mut matches: Vec<WindowInfo> = windows
+        .into_iter()
+        .filter(|w| !w.is_minimized)
+        .filter(|w| app_matcher.as_ref().is_none_or(|m| m.matches(&w.app_name)))
+        .filter(|w| title_matcher.as_ref().is_none_or(|m| m.matches(&w.title)))
+        .collect();
+    matches.sort_by_key(|w| std::cmp::Reverse(w.z));
+
+    if matches.is_empty() {
+        return Err(CaptureError::NoMatchingWindow(
+            "No window matches the given patterns".to_string(),
+        ));
+    }
+
+    match pick {
+        Pick::Unique if matches.len() > 1 => Err(CaptureError::AmbiguousWindow {
+            candidates: matches.iter().map(WindowCandidate::from).collect(),
+        }),
+        Pick::Unique | Pick::Frontmost => {
+            matches.truncate(1);
+            Ok(matches)
+        }
+        Pick::Largest => {
+            let largest = matches
+                .into_iter()
+                .max_by_key(|w| w.width as u64 * w.height as u64)
+                .into_iter()
+                .collect();
+            Ok(largest)
+        }
+        Pick::All => Ok(matches),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{CaptureBackend, SyntheticBackend};
+
+    fn windows() -> Vec<WindowInfo> {
+        SyntheticBackend::default().windows().unwrap()
+    }
+
+    fn ids(windows: &[WindowInfo]) -> Vec<u32> {
+        windows.iter().map(|w| w.id).collect()
+    }
+
+    fn glob(pattern: &str, text: &str) -> bool {
+        Matcher::new(&Pattern::Glob(pattern.to_string()))
+            .unwrap()
+            .matches(text)
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob("notepad*", "Notepad - Untitled"));
+        assert!(glob("*notepad", "Untitled - Notepad"));
+        assert!(glob("*- notepad*", "notes.txt - Notepad++"));
+        assert!(glob("n?tepad", "Notepad"));
+        assert!(glob("*", ""));
+        assert!(!glob("notepad", "Notepad++"));
+        assert!(!glob("*notepad", "Notepad - Untitled"));
+        assert!(!glob("?", ""));
+    }
+
+    #[test]
+    fn glob_star_in_title() {
+        assert!(glob("*notepad", "*Untitled - Notepad"));
+        assert!(glob("*", "*a"));
+        assert!(glob("**untitled*", "*Untitled - Notepad"));
+        assert!(!glob("*untitled", "*Untitled - Notepad"));
+    }
+
+    #[test]
+    fn select_picks() {
+        let editor = Pattern::Exact("editor".to_string());
+        let err = select(windows(), Some(&editor), None, Pick::Unique).unwrap_err();
+        assert!(
+            matches!(err, CaptureError::AmbiguousWindow { ref candidates } if candidates.len() == 2)
+        );
+
+        let front = select(windows(), Some(&editor), None, Pick::Frontmost).unwrap();
+        assert_eq!(ids(&front), [101]);
+        let largest = select(windows(), Some(&editor), None, Pick::Largest).unwrap();
+        assert_eq!(ids(&largest), [101]);
+        let all = select(windows(), Some(&editor), None, Pick::All).unwrap();
+        assert_eq!(ids(&all), [101, 102]);
+    }
+
+    #[test]
+    fn select_skips_minimized() {
+        let notes = Pattern::Glob("*notes".to_string());
+        let err = select(windows(), None, Some(&notes), Pick::All).unwrap_err();
+        assert!(matches!(err, CaptureError::NoMatchingWindow(_)));
+    }
+
+    #[test]
+    fn select_by_regex() {
+        let term = Pattern::Regex("^Term".to_string());
+        let found = select(windows(), None, Some(&term), Pick::Unique).unwrap();
+        assert_eq!(ids(&found), [103]);
+        assert!(select(windows(), None, None, Pick::All).is_err());
+    }
+}
@@ -0,0 +1,388 @@
+use crate::error::CaptureError;
+use crate::files;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Gives up looking for a free `{counter}` value or collision suffix after
+/// this many attempts.
+const MAX_ATTEMPTS: u32 = 100_000;
+
+/// Longest file or directory name produced, in bytes.
+const MAX_COMPONENT_LEN: usize = 200;
+
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Which filename template applies.
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    #[default]
+    Image,
+    Video,
+}
+
+/// What to do when the generated path already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Collision {
+    /// Append ` (2)`, ` (3)`, ... before the extension.
+    #[default]
+    Suffix,
+    Overwrite,
+    /// Fail with `file_exists` so the UI can ask for another name.
+    Ask,
+}
+
+/// Values substituted into a template. Missing values render as `unknown`.
+#[derive(Debug, Clone, Default)]
+pub struct NameFields {
+    pub mode: String,
+    pub monitor: Option<String>,
+    pub app: Option<String>,
+    pub title: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// Renders `template` into path components, one per `/`-separated segment.
+///
+/// Tokens are `{date}`, `{date:<strftime>}`, `{time}`, `{time:<strftime>}`,
+/// `{mode}`, `{monitor}`, `{app}`, `{title}`, `{width}`, `{height}`,
+/// `{counter}` and `{counter:<digits>}`; `{{` and `}}` are literal braces.
+/// Substituted values never introduce extra path segments.
+fn render(
+    template: &str,
+    fields: &NameFields,
+    now: &DateTime<Local>,
+    counter: u32,
+) -> Result<Vec<String>, CaptureError> {
+    let mut components = vec![String::new()];
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                components.last_mut().unwrap().push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                components.last_mut().unwrap().push('}');
+            }
+            '{' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => token.push(c),
+                        None => {
+                            return Err(CaptureError::invalid(format!(
+                                "Unclosed token {{{} in filename template",
+                                token
+                            )))
+                        }
+                    }
+                }
+                let value = token_value(&token, fields, now, counter)?;
+                components
+                    .last_mut()
+                    .unwrap()
+                    .push_str(&value.replace(['/', '\\'], "_"));
+            }
+            '}' => return Err(CaptureError::invalid("Unmatched } in filename template")),
+            '/' | '\\' => components.push(String::new()),
+            c => components.last_mut().unwrap().push(c),
+        }
+    }
+    Ok(components.iter().map(|c| sanitize(c)).collect())
+}
+
+fn token_value(
+    token: &str,
+    fields: &NameFields,
+    now: &DateTime<Local>,
+    counter: u32,
+) -> Result<String, CaptureError> {
+    let (name, arg) = match token.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (token, None),
+    };
+    let text = |value: &Option<String>| value.clone().unwrap_or_else(|| "unknown".to_string());
+    let number =
+        |value: Option<u32>| value.map_or_else(|| "unknown".to_string(), |v| v.to_string());
+    let value = match name {
+        "date" => format_time(now, arg.unwrap_or("%Y-%m-%d"))?,
+        "time" => format_time(now, arg.unwrap_or("%H-%M-%S"))?,
+        "mode" => fields.mode.clone(),
+        "monitor" => text(&fields.monitor),
+        "app" => text(&fields.app),
+        "title" => text(&fields.title),
+        "width" => number(fields.width),
+        "height" => number(fields.height),
+        "counter" => {
+            let digits: usize = match arg {
+                Some(arg) => arg
+                    .parse()
+                    .map_err(|_| CaptureError::invalid(format!("Invalid counter width {}", arg)))?,
+                None => 1,
+            };
+            format!("{:0digits$}", counter, digits = digits.min(10))
+        }
+        _ => {
+            return Err(CaptureError::invalid(format!(
+                "Unknown filename token {{{}}}",
+                token
+            )))
+        }
+    };
+    Ok(value)
+}
+
+fn format_time(now: &DateTime<Local>, format: &str) -> Result<String, CaptureError> {
+    // chrono panics on invalid specifiers when displayed, so validate first
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(CaptureError::invalid(format!(
+            "Invalid date format {} in filename template",
+            format
+        )));
+    }
+    Ok(now.format_with_items(items.into_iter()).to_string())
+}
+
+/// Makes one path component safe on every supported filesystem.
+fn sanitize(component: &str) -> String {
+    let mut clean: String = component
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    if clean.len() > MAX_COMPONENT_LEN {
+        let mut end = MAX_COMPONENT_LEN;
+        while !clean.is_char_boundary(end) {
+            end -= 1;
+        }
+        clean.truncate(end);
+    }
+    // Windows strips trailing dots and spaces, which would alias names
+    let clean = clean.trim().trim_end_matches('.').to_string();
+    if clean.is_empty() || clean == "." || clean == ".." {
+        return "_".to_string();
+    }
+    let stem = clean.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return format!("_{}", clean);
+    }
+    clean
+}
+
+/// Checks that `template` only uses known tokens and valid date formats.
+pub fn validate(template: &str) -> Result<(), CaptureError> {
+    render(template, &NameFields::default(), &Local::now(), 1).map(|_| ())
+}
+
+fn with_extension(components: &[String], extension: &str) -> PathBuf {
+    let mut path: PathBuf = components.iter().collect();
+    let name = format!("{}.{}", components.last().unwrap(), extension);
+    path.set_file_name(name);
+    path
+}
+
+/// Builds the path for a new file named by `template` inside `folder`,
+/// creating any subdirectories the template names.
+///
+/// Without a folder only the file name is returned, as a suggestion for a
+/// save dialog. `{counter}` takes the lowest value whose path is free;
+/// other clashes are resolved according to `collision`. The extension must
+/// be 1-8 ASCII letters or digits.
+pub fn next_path(
+    folder: Option<&Path>,
+    template: &str,
+    fields: &NameFields,
+    extension: &str,
+    collision: Collision,
+) -> Result<PathBuf, CaptureError> {
+    let now = Local::now();
+    let extension = files::check_extension(extension)?;
+    let has_counter = template.contains("{counter");
+
+    let Some(folder) = folder else {
+        let components = render(template, fields, &now, 1)?;
+        return Ok(with_extension(
+            &components[components.len() - 1..],
+            extension,
+        ));
+    };
+
+    let mut counter = 1;
+    let path = loop {
+        let components = render(template, fields, &now, counter)?;
+        let path = folder.join(with_extension(&components, extension));
+        if !has_counter || !path.exists() {
+            break path;
+        }
+        counter += 1;
+        if counter > MAX_ATTEMPTS {
+            return Err(CaptureError::FileExists { path });
+        }
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| CaptureError::io(dir, e))?;
+    }
+    if !path.exists() {
+        return Ok(path);
+    }
+    match collision {
+        Collision::Overwrite => Ok(path),
+        Collision::Ask => Err(CaptureError::FileExists { path }),
+        Collision::Suffix => {
+            let stem = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            for n in 2..MAX_ATTEMPTS {
+                let candidate = path.with_file_name(format!("{} ({}).{}", stem, n, extension));
+                if !candidate.exists() {
+                    return Ok(candidate);
+                }
+            }
+            Err(CaptureError::FileExists { path })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn fields() -> NameFields {
+        NameFields {
+            mode: "window".to_string(),
+            app: Some("Editor".to_string()),
+            title: Some("notes/todo: today".to_string()),
+            width: Some(800),
+            height: Some(600),
+            ..Default::default()
+        }
+    }
+
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 6, 7, 8, 9).unwrap()
+    }
+
+    #[test]
+    fn renders_tokens() {
+        let components = render(
+            "{app}/{date}_{time:%H%M} {mode} {width}x{height} {monitor} #{counter:3} {{x}}",
+            &fields(),
+            &now(),
+            7,
+        )
+        .unwrap();
+        assert_eq!(
+            components,
+            ["Editor", "2024-05-06_0708 window 800x600 unknown #007 {x}"]
+        );
+    }
+
+    #[test]
+    fn values_do_not_add_segments() {
+        let components = render("{title}", &fields(), &now(), 1).unwrap();
+        assert_eq!(components, ["notes_todo_ today"]);
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for template in ["{nope}", "{date", "oops}", "{counter:x}", "{date:%Q}"] {
+            assert!(validate(template).is_err(), "{}", template);
+        }
+        assert!(validate("{date:%Y}/{counter:4}").is_ok());
+    }
+
+    #[test]
+    fn sanitizes_components() {
+        assert_eq!(sanitize("a<b>c?"), "a_b_c_");
+        assert_eq!(sanitize("name. "), "name");
+        assert_eq!(sanitize(".."), "_");
+        assert_eq!(sanitize(""), "_");
+        assert_eq!(sanitize("con.txt"), "_con.txt");
+        assert_eq!(sanitize(&"é".repeat(150)).len(), MAX_COMPONENT_LEN);
+    }
+
+    #[test]
+    fn counter_takes_the_first_free_value() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("shot 1.png"), b"").unwrap();
+        fs::write(dir.path().join("shot 2.png"), b"").unwrap();
+        let path = next_path(
+            Some(dir.path()),
+            "shot {counter}",
+            &fields(),
+            ".png",
+            Collision::Ask,
+        )
+        .unwrap();
+        assert_eq!(path, dir.path().join("shot 3.png"));
+    }
+
+    #[test]
+    fn resolves_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let next = |collision| next_path(Some(dir.path()), "{mode}", &fields(), "png", collision);
+        fs::write(dir.path().join("window.png"), b"").unwrap();
+        fs::write(dir.path().join("window (2).png"), b"").unwrap();
+        assert_eq!(
+            next(Collision::Suffix).unwrap(),
+            dir.path().join("window (3).png")
+        );
+        assert_eq!(
+            next(Collision::Overwrite).unwrap(),
+            dir.path().join("window.png")
+        );
+        assert_eq!(next(Collision::Ask).unwrap_err().code(), "file_exists");
+    }
+
+    #[test]
+    fn rejects_unsafe_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        for extension in ["", "..", "png/../../x", "p/ng", "p\\ng", "toolongext"] {
+            let result = next_path(
+                Some(dir.path()),
+                "{mode}",
+                &fields(),
+                extension,
+                Collision::Suffix,
+            );
+            assert_eq!(result.unwrap_err().code(), "invalid_input", "{}", extension);
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn creates_subdirectories() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = next_path(
+            Some(dir.path()),
+            "{app}/{mode}",
+            &fields(),
+            "png",
+            Collision::Suffix,
+        )
+        .unwrap();
+        assert_eq!(path, dir.path().join("Editor").join("window.png"));
+        assert!(dir.path().join("Editor").is_dir());
+        assert_eq!(
+            next_path(None, "{app}/{mode}", &fields(), "png", Collision::Suffix).unwrap(),
+            PathBuf::from("window.png")
+        );
+    }
+}
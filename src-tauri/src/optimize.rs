@@ -0,0 +1,162 @@
+use crate::error::CaptureError;
+use color_quant::NeuQuant;
+use image::RgbaImage;
+
+/// Lossy reduction to an 8-bit palette before PNG encoding.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct QuantizeOptions {
+    /// Palette size, 2-256.
+    pub colors: u16,
+    /// Floyd-Steinberg error diffusion strength, 0.0 (off) to 1.0 (full).
+    pub dithering: f32,
+    /// NeuQuant sampling factor, 1 (best) to 30 (fastest).
+    pub speed: u8,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self {
+            colors: 256,
+            dithering: 1.0,
+            speed: 10,
+        }
+    }
+}
+
+/// Re-compresses a PNG with oxipng. Pixels are unchanged; `level` is the
+/// oxipng preset, 0 (fast) to 6 (smallest).
+pub fn optimize_png(data: &[u8], level: u8) -> Result<Vec<u8>, CaptureError> {
+    let options = oxipng::Options::from_preset(level.min(6));
+    oxipng::optimize_from_memory(data, &options)
+        .map_err(|e| CaptureError::EncodeFailed(e.to_string()))
+}
+
+/// Quantizes `image` to a palette and encodes it as an indexed PNG.
+pub fn quantize_png(image: &RgbaImage, options: &QuantizeOptions) -> Result<Vec<u8>, CaptureError> {
+    let colors = options.colors.clamp(2, 256) as usize;
+    let quant = NeuQuant::new(options.speed.clamp(1, 30) as i32, colors, image.as_raw());
+    let indices = dither(image, &quant, options.dithering.clamp(0.0, 1.0));
+
+    let palette_rgba = quant.color_map_rgba();
+    let palette: Vec<u8> = palette_rgba
+        .chunks_exact(4)
+        .flat_map(|c| [c[0], c[1], c[2]])
+        .collect();
+    let alphas: Vec<u8> = palette_rgba.chunks_exact(4).map(|c| c[3]).collect();
+
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, image.width(), image.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    // The png crate defaults to its fast single-pass compressor and the Sub
+    // filter, which does poorly on palette indices.
+    encoder.set_compression(png::Compression::Best);
+    encoder.set_filter(png::FilterType::NoFilter);
+    encoder.set_palette(palette);
+    if alphas.iter().any(|&a| a != 255) {
+        encoder.set_trns(alphas);
+    }
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+    writer
+        .write_image_data(&indices)
+        .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+    writer
+        .finish()
+        .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+    Ok(buffer)
+}
+
+/// Maps every pixel to a palette index, spreading the rounding error to
+/// neighbouring pixels scaled by `strength`.
+pub fn dither(image: &RgbaImage, quant: &NeuQuant, strength: f32) -> Vec<u8> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let palette = quant.color_map_rgba();
+    let mut indices = Vec::with_capacity(width * height);
+
+    if strength == 0.0 {
+        for pixel in image.pixels() {
+            indices.push(quant.index_of(&pixel.0) as u8);
+        }
+        return indices;
+    }
+
+    // Error carried into the current and next row, per RGBA channel
+    let mut current = vec![[0f32; 4]; width + 2];
+    let mut next = vec![[0f32; 4]; width + 2];
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = image.get_pixel(x as u32, y as u32).0;
+            let mut wanted = [0u8; 4];
+            for c in 0..4 {
+                wanted[c] = (pixel[c] as f32 + current[x + 1][c])
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+            let index = quant.index_of(&wanted);
+            indices.push(index as u8);
+
+            let chosen = &palette[index * 4..index * 4 + 4];
+            for c in 0..4 {
+                let error = (wanted[c] as f32 - chosen[c] as f32) * strength;
+                current[x + 2][c] += error * 7.0 / 16.0;
+                next[x][c] += error * 3.0 / 16.0;
+                next[x + 1][c] += error * 5.0 / 16.0;
+                next[x + 2][c] += error * 1.0 / 16.0;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|e| *e = [0.0; 4]);
+    }
+    indices
+}
+
+/// 4x4 Bayer threshold matrix, values 0-15.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Maps every pixel to a palette index after nudging it by a fixed
+/// per-position threshold. Unlike error diffusion, a pixel's index depends
+/// only on its own color and position, so unchanged areas stay identical
+/// from frame to frame. `origin` is where `image` sits in the full frame,
+/// keeping the pattern aligned when only a changed part is encoded.
+pub fn dither_ordered(image: &RgbaImage, quant: &NeuQuant, origin: (u32, u32)) -> Vec<u8> {
+    // About half the distance between colors of an evenly spread palette;
+    // more turns flat areas that match a palette color into noise
+    let colors = (quant.color_map_rgba().len() / 4) as f32;
+    let spread = (128.0 / colors.cbrt()).min(32.0);
+    image
+        .enumerate_pixels()
+        .map(|(x, y, pixel)| {
+            let (x, y) = ((x + origin.0) as usize % 4, (y + origin.1) as usize % 4);
+            let offset = (BAYER[y][x] as f32 / 16.0 - 0.5) * spread;
+            let mut wanted = pixel.0;
+            for c in wanted.iter_mut().take(3) {
+                *c = (*c as f32 + offset).round().clamp(0.0, 255.0) as u8;
+            }
+            quant.index_of(&wanted) as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{imageops, Rgba};
+
+    #[test]
+    fn ordered_dither_follows_the_frame() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, 90, 255])
+        });
+        let quant = NeuQuant::new(10, 16, image.as_raw());
+        let full = dither_ordered(&image, &quant, (0, 0));
+        let part = imageops::crop_imm(&image, 5, 3, 8, 8).to_image();
+        let indices = dither_ordered(&part, &quant, (5, 3));
+        for (i, index) in indices.iter().enumerate() {
+            let (x, y) = (5 + i % 8, 3 + i / 8);
+            assert_eq!(*index, full[y * 16 + x]);
+        }
+    }
+}
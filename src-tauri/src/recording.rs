@@ -0,0 +1,669 @@
+use crate::error::CaptureError;
+use crate::gif_writer::{GifOptions, GifWriter};
+use crate::region;
+use crate::webm::WebmWriter;
+use image::RgbaImage;
+use rav1e::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Frames waiting for the encoder. Anything beyond this is dropped rather
+/// than slowing down capture.
+const QUEUE_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    /// AV1 video in WebM.
+    #[default]
+    Webm,
+    /// Animated GIF.
+    Gif,
+}
+
+impl RecordingFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Webm => "webm",
+            RecordingFormat::Gif => "gif",
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RecordingOptions {
+    pub format: RecordingFormat,
+    /// Highest frame rate recorded. Frames captured sooner are skipped.
+    pub fps: u32,
+    /// Frames larger than this are scaled down, keeping the aspect ratio.
+    pub max_width: u32,
+    pub max_height: u32,
+    /// rav1e speed preset, 0 (slowest, smallest) to 10 (fastest).
+    pub speed: u8,
+    /// rav1e quantizer, 0 (lossless) to 255.
+    pub quantizer: u8,
+    /// Palette and dithering, for GIF only.
+    pub gif: GifOptions,
+    /// How often the webview gets a preview frame while recording.
+    pub preview_fps: u32,
+    /// Stop automatically after this much recorded time, not counting pauses.
+    pub max_duration_secs: Option<u64>,
+    /// Stop automatically once the file reaches this size.
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        Self {
+            format: RecordingFormat::Webm,
+            fps: 30,
+            max_width: 1920,
+            max_height: 1080,
+            speed: 10,
+            quantizer: 100,
+            gif: GifOptions::default(),
+            preview_fps: 2,
+            max_duration_secs: None,
+            max_bytes: None,
+        }
+    }
+}
+
+impl RecordingOptions {
+    pub fn validate(&self) -> Result<(), CaptureError> {
+        if !(1..=60).contains(&self.fps) {
+            return Err(CaptureError::invalid("fps must be between 1 and 60"));
+        }
+        // GIF delays are in hundredths of a second and players slow down
+        // anything shorter than two
+        if self.format == RecordingFormat::Gif && self.fps > 50 {
+            return Err(CaptureError::invalid("GIF fps must be at most 50"));
+        }
+        if self.max_duration_secs == Some(0) || self.max_bytes == Some(0) {
+            return Err(CaptureError::invalid("Limits must be greater than zero"));
+        }
+        if self.max_width > u16::MAX as u32 || self.max_height > u16::MAX as u32 {
+            return Err(CaptureError::invalid("Maximum size is too large"));
+        }
+        if self.max_width < 16 || self.max_height < 16 {
+            return Err(CaptureError::invalid("Maximum size must be at least 16x16"));
+        }
+        if self.speed > 10 {
+            return Err(CaptureError::invalid("speed must be between 0 and 10"));
+        }
+        if !(1..=30).contains(&self.preview_fps) {
+            return Err(CaptureError::invalid("previewFps must be between 1 and 30"));
+        }
+        self.gif.validate()
+    }
+
+    pub fn preview_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.preview_fps
+    }
+}
+
+/// Where a recording is in its life cycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingState {
+    #[default]
+    Idle,
+    Recording,
+    Paused,
+    /// Stopped; the encoder is writing the last frames.
+    Finalizing,
+}
+
+/// Why a recording stopped.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StopReason {
+    Requested,
+    MaxDuration,
+    MaxSize,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingStats {
+    /// Frames handed to the encoder.
+    pub frames: u64,
+    /// Frames skipped because the encoder fell behind.
+    pub dropped: u64,
+    pub bytes: u64,
+    pub duration_ms: u64,
+}
+
+/// Converts RGBA to 8-bit YUV 4:2:0 with BT.709 limited-range coefficients.
+/// Width and height must be even.
+fn rgba_to_yuv420(image: &RgbaImage) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let rgba = image.as_raw();
+    let luma = |r: i32, g: i32, b: i32| (((47 * r + 157 * g + 16 * b + 128) >> 8) + 16) as u8;
+
+    let mut y = Vec::with_capacity(width * height);
+    for pixel in rgba.chunks_exact(4) {
+        y.push(luma(pixel[0] as i32, pixel[1] as i32, pixel[2] as i32));
+    }
+
+    let mut u = Vec::with_capacity(width * height / 4);
+    let mut v = Vec::with_capacity(width * height / 4);
+    for row in (0..height).step_by(2) {
+        for col in (0..width).step_by(2) {
+            let (mut r, mut g, mut b) = (0i32, 0i32, 0i32);
+            for (dy, dx) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                let i = ((row + dy) * width + col + dx) * 4;
+                r += rgba[i] as i32;
+                g += rgba[i + 1] as i32;
+                b += rgba[i + 2] as i32;
+            }
+            let (r, g, b) = ((r + 2) / 4, (g + 2) / 4, (b + 2) / 4);
+            u.push((((-26 * r - 87 * g + 113 * b + 128) >> 8) + 128) as u8);
+            v.push((((112 * r - 102 * g - 10 * b + 128) >> 8) + 128) as u8);
+        }
+    }
+    (y, u, v)
+}
+
+/// Whether a frame taken at `now` should be kept when the last kept one was
+/// taken at `last` and frames are wanted every `interval`.
+pub fn frame_due(last: Option<Instant>, now: Instant, interval: Duration) -> bool {
+    // A little slack so a 30 fps capture loop is not cut to 15 fps
+    last.is_none_or(|last| now.duration_since(last) >= interval.mul_f32(0.9))
+}
+
+/// Smallest width and height rav1e accepts.
+const MIN_AV1_SIZE: u32 = 16;
+
+/// Largest even size that fits a `width` x `height` frame into the limits
+/// without upscaling.
+pub fn fit_size(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    let scale = (max_width as f64 / width as f64)
+        .min(max_height as f64 / height as f64)
+        .min(1.0);
+    let even = |n: f64| ((n as u32) & !1).max(2);
+    (even(width as f64 * scale), even(height as f64 * scale))
+}
+
+/// Turns frames into file contents, returned as they become available.
+trait FrameEncoder {
+    /// Adds a frame shown from `timestamp_ms` on.
+    fn encode(&mut self, image: &RgbaImage, timestamp_ms: u64) -> Result<Vec<u8>, CaptureError>;
+    /// Returns whatever is left once the last frame was added.
+    fn finish(&mut self) -> Result<Vec<u8>, CaptureError>;
+}
+
+impl FrameEncoder for GifWriter {
+    fn encode(&mut self, image: &RgbaImage, timestamp_ms: u64) -> Result<Vec<u8>, CaptureError> {
+        self.frame(image, timestamp_ms)
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, CaptureError> {
+        GifWriter::finish(self)
+    }
+}
+
+/// AV1 encoder producing a WebM stream.
+struct Av1Encoder {
+    context: Context<u8>,
+    webm: WebmWriter,
+    width: u32,
+}
+
+impl Av1Encoder {
+    fn new(width: u32, height: u32, options: &RecordingOptions) -> Result<Self, CaptureError> {
+        let mut config = EncoderConfig::with_speed_preset(options.speed);
+        config.width = width as usize;
+        config.height = height as usize;
+        config.time_base = Rational::new(1, 1000);
+        config.bit_depth = 8;
+        config.chroma_sampling = ChromaSampling::Cs420;
+        config.pixel_range = PixelRange::Limited;
+        config.color_description = Some(ColorDescription {
+            color_primaries: ColorPrimaries::BT709,
+            transfer_characteristics: TransferCharacteristics::BT709,
+            matrix_coefficients: MatrixCoefficients::BT709,
+        });
+        // Packets must come out as frames go in, or a stop loses the tail
+        config.low_latency = true;
+        config.quantizer = options.quantizer as usize;
+        config.max_key_frame_interval = options.fps as u64 * 10;
+        config.min_key_frame_interval = config.max_key_frame_interval.min(12);
+
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let context: Context<u8> = Config::new()
+            .with_encoder_config(config)
+            .with_threads(threads)
+            .new_context()
+            .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+        let webm = WebmWriter::new(width, height, context.container_sequence_header());
+        Ok(Self {
+            context,
+            webm,
+            width,
+        })
+    }
+
+    fn drain(&mut self) -> Result<Vec<u8>, CaptureError> {
+        let mut out = Vec::new();
+        loop {
+            match self.context.receive_packet() {
+                Ok(packet) => {
+                    let timestamp_ms = packet
+                        .opaque
+                        .and_then(|o| o.downcast::<u64>().ok())
+                        .map_or(0, |t| *t);
+                    let keyframe = packet.frame_type == FrameType::KEY;
+                    out.extend(self.webm.frame(&packet.data, timestamp_ms, keyframe));
+                }
+                Err(EncoderStatus::Encoded) => continue,
+                Err(EncoderStatus::NeedMoreData | EncoderStatus::LimitReached) => break,
+                Err(e) => return Err(CaptureError::EncodeFailed(e.to_string())),
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl FrameEncoder for Av1Encoder {
+    fn encode(&mut self, image: &RgbaImage, timestamp_ms: u64) -> Result<Vec<u8>, CaptureError> {
+        let (y, u, v) = rgba_to_yuv420(image);
+        let mut frame = self.context.new_frame();
+        let strides = [
+            self.width as usize,
+            self.width as usize / 2,
+            self.width as usize / 2,
+        ];
+        for ((plane, data), stride) in frame.planes.iter_mut().zip([y, u, v]).zip(strides) {
+            plane.copy_from_raw_u8(&data, stride, 1);
+        }
+        let params = FrameParameters {
+            opaque: Some(Opaque::new(timestamp_ms)),
+            ..Default::default()
+        };
+        self.context
+            .send_frame((frame, params))
+            .map_err(|e| CaptureError::EncodeFailed(e.to_string()))?;
+        self.drain()
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, CaptureError> {
+        self.context.flush();
+        self.drain()
+    }
+}
+
+/// Encodes `frames` with their capture times in the given format, handing
+/// the output to `sink` as it is produced. The first frame decides the
+/// output size; later frames are letterboxed to match, so a window resized
+/// mid-recording is not stretched.
+pub fn encode_frames(
+    frames: impl IntoIterator<Item = Result<(RgbaImage, Instant), CaptureError>>,
+    options: &RecordingOptions,
+    mut sink: impl FnMut(&[u8]) -> Result<(), CaptureError>,
+) -> Result<RecordingStats, CaptureError> {
+    let mut encoder: Option<(Box<dyn FrameEncoder>, u32, u32)> = None;
+    let mut started = None;
+    let mut stats = RecordingStats::default();
+    for frame in frames {
+        let (image, taken_at) = frame?;
+        let started = *started.get_or_insert(taken_at);
+        let (encoder, width, height) = match &mut encoder {
+            Some(encoder) => encoder,
+            None => {
+                let (mut width, mut height) = fit_size(
+                    image.width(),
+                    image.height(),
+                    options.max_width,
+                    options.max_height,
+                );
+                if options.format == RecordingFormat::Webm {
+                    // rav1e rejects frames under 16 pixels on a side, so
+                    // thinner areas are letterboxed up to it
+                    width = width.max(MIN_AV1_SIZE);
+                    height = height.max(MIN_AV1_SIZE);
+                }
+                let new: Box<dyn FrameEncoder> = match options.format {
+                    RecordingFormat::Webm => Box::new(Av1Encoder::new(width, height, options)?),
+                    RecordingFormat::Gif => Box::new(GifWriter::new(options.gif.clone())),
+                };
+                encoder.insert((new, width, height))
+            }
+        };
+        // Also covers the monitor changing resolution mid-recording
+        let image = region::letterbox(image, *width, *height)?;
+        let timestamp_ms = taken_at.duration_since(started).as_millis() as u64;
+        let data = encoder.encode(&image, timestamp_ms)?;
+        stats.bytes += data.len() as u64;
+        sink(&data)?;
+        stats.frames += 1;
+        stats.duration_ms = timestamp_ms;
+    }
+    if let Some((mut encoder, _, _)) = encoder {
+        let data = encoder.finish()?;
+        stats.bytes += data.len() as u64;
+        sink(&data)?;
+    }
+    Ok(stats)
+}
+
+/// A recording fed with frames by the capture loop.
+///
+/// Encoding runs on its own thread behind a short queue, so a slow encoder
+/// drops frames instead of stalling capture. Output is handed to the sink as
+/// it is produced.
+pub struct Recorder {
+    pub session: u64,
+    pub options: RecordingOptions,
+    frames: SyncSender<(RgbaImage, Instant)>,
+    encoder: JoinHandle<Result<RecordingStats, CaptureError>>,
+    frame_interval: Duration,
+    last_frame: Option<Instant>,
+    dropped: u64,
+    started: Instant,
+    paused_at: Option<Instant>,
+    /// Time spent paused so far, left out of the timestamps.
+    paused_for: Duration,
+    /// Bytes handed to the sink so far.
+    bytes: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    pub fn start(
+        session: u64,
+        options: RecordingOptions,
+        mut sink: impl FnMut(&[u8]) -> Result<(), CaptureError> + Send + 'static,
+    ) -> Result<Self, CaptureError> {
+        options.validate()?;
+        let (frames, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        let encoder_options = options.clone();
+        let bytes = Arc::new(AtomicU64::new(0));
+        let written = bytes.clone();
+        let encoder = thread::spawn(move || {
+            let sink = |data: &[u8]| {
+                sink(data)?;
+                written.fetch_add(data.len() as u64, Ordering::Relaxed);
+                Ok(())
+            };
+            encode_frames(receiver.into_iter().map(Ok), &encoder_options, sink)
+        });
+        Ok(Self {
+            session,
+            frame_interval: Duration::from_secs(1) / options.fps,
+            options,
+            frames,
+            encoder,
+            last_frame: None,
+            dropped: 0,
+            started: Instant::now(),
+            paused_at: None,
+            paused_for: Duration::ZERO,
+            bytes,
+        })
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    /// Stops taking frames until `resume`.
+    pub fn pause(&mut self) {
+        self.paused_at.get_or_insert_with(Instant::now);
+    }
+
+    /// Takes frames again. The pause does not show up in the recording.
+    pub fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.paused_for += paused_at.elapsed();
+        }
+    }
+
+    /// Recorded time so far, not counting pauses.
+    pub fn duration(&self) -> Duration {
+        let now = self.paused_at.unwrap_or_else(Instant::now);
+        now.duration_since(self.started)
+            .saturating_sub(self.paused_for)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Which limit from the options the recording has reached, if any.
+    pub fn limit_reached(&self) -> Option<StopReason> {
+        let max_duration = self.options.max_duration_secs.map(Duration::from_secs);
+        if max_duration.is_some_and(|max| self.duration() >= max) {
+            return Some(StopReason::MaxDuration);
+        }
+        if self
+            .options
+            .max_bytes
+            .is_some_and(|max| self.bytes() >= max)
+        {
+            return Some(StopReason::MaxSize);
+        }
+        None
+    }
+
+    /// Queues a frame captured at `taken_at`, skipping it when paused, when
+    /// it comes sooner than the frame rate allows or when the encoder is
+    /// busy.
+    pub fn push(&mut self, image: RgbaImage, taken_at: Instant) {
+        if self.is_paused() {
+            return;
+        }
+        if !frame_due(self.last_frame, taken_at, self.frame_interval) {
+            return;
+        }
+        // Shift frames after a pause back so the recording has no gap
+        let shown_at = taken_at.checked_sub(self.paused_for).unwrap_or(taken_at);
+        match self.frames.try_send((image, shown_at)) {
+            Ok(()) => self.last_frame = Some(taken_at),
+            Err(TrySendError::Full(_)) => self.dropped += 1,
+            // The encoder failed; `finish` reports why
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    /// Encodes the queued frames, flushes the encoder and returns the stats.
+    pub fn finish(self) -> Result<RecordingStats, CaptureError> {
+        drop(self.frames);
+        let mut stats = self
+            .encoder
+            .join()
+            .map_err(|_| CaptureError::EncodeFailed("Encoder thread panicked".to_string()))??;
+        stats.dropped = self.dropped;
+        Ok(stats)
+    }
+}
+
+/// The app's recording and where it is in its life cycle.
+///
+/// Idle until a recorder is started, Recording or Paused while it takes
+/// frames, and Finalizing from the moment it is stopped until its encoder
+/// is done. A new recording can only start from Idle.
+#[derive(Default)]
+pub struct RecordingSlot {
+    recorder: Option<Recorder>,
+    /// The stream feeding the recorder.
+    stream: Option<u64>,
+    finalizing: bool,
+}
+
+impl RecordingSlot {
+    pub fn state(&self) -> RecordingState {
+        match &self.recorder {
+            Some(recorder) if recorder.is_paused() => RecordingState::Paused,
+            Some(_) => RecordingState::Recording,
+            None if self.finalizing => RecordingState::Finalizing,
+            None => RecordingState::Idle,
+        }
+    }
+
+    /// The recorder while it takes frames, i.e. Recording or Paused.
+    pub fn recorder(&mut self) -> Option<&mut Recorder> {
+        self.recorder.as_mut()
+    }
+
+    /// The stream feeding the recorder, while there is one.
+    pub fn stream(&self) -> Option<u64> {
+        self.recorder.as_ref().and(self.stream)
+    }
+
+    /// Idle to Recording, fed by `stream`.
+    pub fn start(&mut self, recorder: Recorder, stream: u64) -> Result<(), CaptureError> {
+        self.expect(RecordingState::Idle)?;
+        self.recorder = Some(recorder);
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Recording to Paused.
+    pub fn pause(&mut self) -> Result<(), CaptureError> {
+        self.expect(RecordingState::Recording)?;
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.pause();
+        }
+        Ok(())
+    }
+
+    /// Paused to Recording.
+    pub fn resume(&mut self) -> Result<(), CaptureError> {
+        self.expect(RecordingState::Paused)?;
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.resume();
+        }
+        Ok(())
+    }
+
+    /// Recording or Paused to Finalizing. The caller finishes the returned
+    /// recorder and then calls `finalized`.
+    pub fn stop(&mut self) -> Result<Recorder, CaptureError> {
+        let recorder = self
+            .recorder
+            .take()
+            .ok_or_else(|| CaptureError::invalid("No recording is running"))?;
+        self.stream = None;
+        self.finalizing = true;
+        Ok(recorder)
+    }
+
+    /// Finalizing to Idle.
+    pub fn finalized(&mut self) {
+        self.finalizing = false;
+    }
+
+    pub fn expect(&self, state: RecordingState) -> Result<(), CaptureError> {
+        let current = self.state();
+        if current == state {
+            return Ok(());
+        }
+        let message = match current {
+            RecordingState::Idle => "No recording is running",
+            RecordingState::Recording => "A recording is already running",
+            RecordingState::Paused => "The recording is paused",
+            RecordingState::Finalizing => "The last recording is still being written",
+        };
+        Err(CaptureError::invalid(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(image: RgbaImage, options: &RecordingOptions) -> RecordingStats {
+        let start = Instant::now();
+        let frames = (0..3).map(|i| Ok((image.clone(), start + Duration::from_millis(i * 100))));
+        let mut output = Vec::new();
+        let stats = encode_frames(frames, options, |data| {
+            output.extend_from_slice(data);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(stats.bytes, output.len() as u64);
+        stats
+    }
+
+    #[test]
+    fn fit_size_keeps_even_sizes() {
+        assert_eq!(fit_size(1920, 1080, 1920, 1080), (1920, 1080));
+        assert_eq!(fit_size(3840, 2160, 1920, 1080), (1920, 1080));
+        assert_eq!(fit_size(801, 601, 1920, 1080), (800, 600));
+        assert_eq!(fit_size(1000, 3, 1920, 1080), (1000, 2));
+    }
+
+    #[test]
+    fn frame_due_allows_slack() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(1) / 30;
+        assert!(frame_due(None, start, interval));
+        assert!(frame_due(Some(start), start + interval, interval));
+        assert!(frame_due(
+            Some(start),
+            start + Duration::from_millis(31),
+            interval
+        ));
+        assert!(!frame_due(
+            Some(start),
+            start + Duration::from_millis(20),
+            interval
+        ));
+    }
+
+    fn recorder() -> Recorder {
+        let options = RecordingOptions {
+            format: RecordingFormat::Gif,
+            ..Default::default()
+        };
+        Recorder::start(1, options, |_| Ok(())).unwrap()
+    }
+
+    #[test]
+    fn slot_moves_through_its_states() {
+        let mut slot = RecordingSlot::default();
+        assert_eq!(slot.state(), RecordingState::Idle);
+        assert!(slot.pause().is_err());
+        assert!(slot.stop().is_err());
+
+        slot.start(recorder(), 5).unwrap();
+        assert_eq!(slot.state(), RecordingState::Recording);
+        assert_eq!(slot.stream(), Some(5));
+        assert!(slot.start(recorder(), 6).is_err());
+        assert!(slot.resume().is_err());
+
+        slot.pause().unwrap();
+        assert_eq!(slot.state(), RecordingState::Paused);
+        assert!(slot.pause().is_err());
+        slot.resume().unwrap();
+        assert_eq!(slot.state(), RecordingState::Recording);
+
+        let recorder = slot.stop().unwrap();
+        assert_eq!(slot.state(), RecordingState::Finalizing);
+        assert_eq!(slot.stream(), None);
+        assert!(slot.start(self::recorder(), 7).is_err());
+        recorder.finish().unwrap();
+        slot.finalized();
+        assert_eq!(slot.state(), RecordingState::Idle);
+    }
+
+    #[test]
+    fn webm_pads_thin_areas() {
+        let stats = encode(RgbaImage::new(200, 6), &RecordingOptions::default());
+        assert_eq!(stats.frames, 3);
+    }
+
+    #[test]
+    fn webm_at_one_fps() {
+        let options = RecordingOptions {
+            fps: 1,
+            ..Default::default()
+        };
+        let stats = encode(RgbaImage::new(64, 48), &options);
+        assert_eq!(stats.frames, 3);
+    }
+}
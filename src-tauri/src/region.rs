@@ -0,0 +1,313 @@
+use crate::backend::{CaptureBackend, MonitorInfo};
+use crate::error::CaptureError;
+use image::{imageops, Rgba, RgbaImage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rect {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    fn right(&self) -> i64 {
+        self.x as i64 + self.width as i64
+    }
+
+    fn bottom(&self) -> i64 {
+        self.y as i64 + self.height as i64
+    }
+
+    fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let left = self.x.max(other.x);
+        let top = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        if right <= left as i64 || bottom <= top as i64 {
+            return None;
+        }
+        Some(Rect {
+            x: left,
+            y: top,
+            width: (right - left as i64) as u32,
+            height: (bottom - top as i64) as u32,
+        })
+    }
+}
+
+/// Crops `image` to a rectangle given in image pixels. The rectangle must lie
+/// entirely inside the image.
+pub fn crop(
+    mut image: RgbaImage,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage, CaptureError> {
+    if width == 0 || height == 0 {
+        return Err(CaptureError::invalid("Region is empty"));
+    }
+    let fits_x = x.checked_add(width).is_some_and(|r| r <= image.width());
+    let fits_y = y.checked_add(height).is_some_and(|b| b <= image.height());
+    if !fits_x || !fits_y {
+        return Err(CaptureError::invalid(format!(
+            "Region {}x{} at ({}, {}) is outside the {}x{} capture",
+            width,
+            height,
+            x,
+            y,
+            image.width(),
+            image.height()
+        )));
+    }
+    Ok(imageops::crop(&mut image, x, y, width, height).to_image())
+}
+
+/// Where a monitor's pixels landed in a stitched capture, in output pixels.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MonitorPlacement {
+    pub monitor_id: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Captures a rectangle in virtual-desktop coordinates, stitching together
+/// every monitor it overlaps. The rectangle is first cut down to the
+/// bounding box of all monitors; areas inside it not covered by any monitor
+/// stay transparent.
+pub fn capture_desktop_region(
+    backend: &dyn CaptureBackend,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage, CaptureError> {
+    if width == 0 || height == 0 {
+        return Err(CaptureError::invalid("Region is empty"));
+    }
+    let region = Rect {
+        x,
+        y,
+        width,
+        height,
+    };
+    Ok(composite(backend, region)?.0)
+}
+
+/// Scales `image` to fit `width` x `height` without changing its aspect
+/// ratio, centered on black. A pixel of rounding is stretched rather than
+/// barred.
+pub fn letterbox(image: RgbaImage, width: u32, height: u32) -> Result<RgbaImage, CaptureError> {
+    if width == 0 || height == 0 {
+        return Err(CaptureError::invalid("Output size is empty"));
+    }
+    if image.width() == 0 || image.height() == 0 {
+        return Err(CaptureError::CaptureFailed(
+            "Captured image is empty".into(),
+        ));
+    }
+    if image.dimensions() == (width, height) {
+        return Ok(image);
+    }
+    let scale = (width as f64 / image.width() as f64).min(height as f64 / image.height() as f64);
+    let fit = |size: u32, target: u32| {
+        let size = ((size as f64 * scale).round() as u32).clamp(1, target);
+        if target - size <= 1 {
+            target
+        } else {
+            size
+        }
+    };
+    let (fit_width, fit_height) = (fit(image.width(), width), fit(image.height(), height));
+    let scaled = imageops::resize(
+        &image,
+        fit_width,
+        fit_height,
+        imageops::FilterType::Triangle,
+    );
+    let mut out = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    imageops::replace(
+        &mut out,
+        &scaled,
+        ((width - fit_width) / 2) as i64,
+        ((height - fit_height) / 2) as i64,
+    );
+    Ok(out)
+}
+
+/// Captures every monitor into one virtual-desktop image, returning the
+/// placement of each monitor so the UI can draw their boundaries.
+pub fn capture_all_monitors(
+    backend: &dyn CaptureBackend,
+) -> Result<(RgbaImage, Vec<MonitorPlacement>), CaptureError> {
+    let bounds =
+        desktop_bounds(&backend.monitors()?).ok_or(CaptureError::MonitorNotFound { id: None })?;
+    composite(backend, bounds)
+}
+
+/// The bounding box of every monitor, or `None` without monitors.
+fn desktop_bounds(monitors: &[MonitorInfo]) -> Option<Rect> {
+    let left = monitors.iter().map(|m| m.x).min()?;
+    let top = monitors.iter().map(|m| m.y).min()?;
+    let right = monitors.iter().map(|m| m.x as i64 + m.width as i64).max()?;
+    let bottom = monitors
+        .iter()
+        .map(|m| m.y as i64 + m.height as i64)
+        .max()?;
+    Some(Rect {
+        x: left,
+        y: top,
+        width: (right - left as i64).max(0) as u32,
+        height: (bottom - top as i64).max(0) as u32,
+    })
+}
+
+/// Stitches the parts of every monitor overlapping `region` into one image.
+///
+/// Monitor geometry may be in logical units while captures are in physical
+/// pixels; the output uses the highest pixel density among the overlapped
+/// monitors and rescales the others to match.
+fn composite(
+    backend: &dyn CaptureBackend,
+    region: Rect,
+) -> Result<(RgbaImage, Vec<MonitorPlacement>), CaptureError> {
+    let monitors = backend.monitors()?;
+    // Clipping first keeps an oversized request from allocating more than
+    // the desktop holds
+    let Some(region) = desktop_bounds(&monitors).and_then(|d| region.intersect(&d)) else {
+        return Err(CaptureError::invalid("Region does not overlap any monitor"));
+    };
+    let mut parts = Vec::new();
+    let mut scale: f64 = 1.0;
+    for monitor in monitors {
+        let bounds = Rect {
+            x: monitor.x,
+            y: monitor.y,
+            width: monitor.width,
+            height: monitor.height,
+        };
+        let Some(overlap) = region.intersect(&bounds) else {
+            continue;
+        };
+        let image = backend.capture_monitor(monitor.id)?;
+        let monitor_scale = image.width() as f64 / monitor.width.max(1) as f64;
+        scale = scale.max(monitor_scale);
+        parts.push((monitor.id, bounds, overlap, image, monitor_scale));
+    }
+    if parts.is_empty() {
+        return Err(CaptureError::invalid("Region does not overlap any monitor"));
+    }
+
+    let to_px = |v: f64, s: f64| (v * s).round() as u32;
+    let mut canvas = RgbaImage::new(
+        to_px(region.width as f64, scale).max(1),
+        to_px(region.height as f64, scale).max(1),
+    );
+    let mut placements = Vec::with_capacity(parts.len());
+
+    for (monitor_id, bounds, overlap, mut image, monitor_scale) in parts {
+        let sx = to_px((overlap.x - bounds.x) as f64, monitor_scale).min(image.width());
+        let sy = to_px((overlap.y - bounds.y) as f64, monitor_scale).min(image.height());
+        let sw = to_px(overlap.width as f64, monitor_scale).min(image.width() - sx);
+        let sh = to_px(overlap.height as f64, monitor_scale).min(image.height() - sy);
+        if sw == 0 || sh == 0 {
+            continue;
+        }
+        let mut part = imageops::crop(&mut image, sx, sy, sw, sh).to_image();
+
+        let dw = to_px(overlap.width as f64, scale).max(1);
+        let dh = to_px(overlap.height as f64, scale).max(1);
+        if part.dimensions() != (dw, dh) {
+            part = imageops::resize(&part, dw, dh, imageops::FilterType::Lanczos3);
+        }
+
+        let dx = to_px((overlap.x - region.x) as f64, scale);
+        let dy = to_px((overlap.y - region.y) as f64, scale);
+        imageops::replace(&mut canvas, &part, dx as i64, dy as i64);
+        placements.push(MonitorPlacement {
+            monitor_id,
+            x: dx,
+            y: dy,
+            width: dw,
+            height: dh,
+        });
+    }
+
+    Ok((canvas, placements))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SyntheticBackend;
+
+    #[test]
+    fn crop_checks_bounds() {
+        let image = SyntheticBackend::test_pattern(100, 50, 1);
+        let part = crop(image.clone(), 10, 20, 30, 30).unwrap();
+        assert_eq!(part.dimensions(), (30, 30));
+        assert_eq!(part.get_pixel(0, 0), image.get_pixel(10, 20));
+        assert!(crop(image.clone(), 80, 0, 30, 10).is_err());
+        assert!(crop(image.clone(), 0, 0, 0, 10).is_err());
+        assert!(crop(image, u32::MAX, 0, 10, 10).is_err());
+    }
+
+    #[test]
+    fn composite_spans_monitors() {
+        let backend = SyntheticBackend::default();
+        let image = capture_desktop_region(&backend, 1900, 0, 40, 1080).unwrap();
+        assert_eq!(image.dimensions(), (40, 1080));
+        let primary = backend.capture_monitor(1).unwrap();
+        let secondary = backend.capture_monitor(2).unwrap();
+        assert_eq!(image.get_pixel(0, 0), primary.get_pixel(1900, 0));
+        assert_eq!(image.get_pixel(20, 5), secondary.get_pixel(0, 5));
+        // Below the shorter secondary monitor nothing is captured
+        assert_eq!(image.get_pixel(30, 1050), &Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn all_monitors_cover_the_desktop() {
+        let (image, placements) = capture_all_monitors(&SyntheticBackend::default()).unwrap();
+        assert_eq!(image.dimensions(), (3200, 1080));
+        let ids: Vec<u32> = placements.iter().map(|p| p.monitor_id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!((placements[1].x, placements[1].width), (1920, 1280));
+    }
+
+    #[test]
+    fn oversized_region_is_clipped_to_the_desktop() {
+        let backend = SyntheticBackend::default();
+        let image = capture_desktop_region(&backend, 3000, -100, u32::MAX, u32::MAX).unwrap();
+        assert_eq!(image.dimensions(), (200, 1080));
+        let image = capture_desktop_region(&backend, i32::MIN, i32::MIN, u32::MAX, u32::MAX);
+        assert_eq!(image.unwrap().dimensions(), (3200, 1080));
+    }
+
+    #[test]
+    fn region_off_the_desktop_fails() {
+        let backend = SyntheticBackend::default();
+        assert!(capture_desktop_region(&backend, -500, -500, 100, 100).is_err());
+        assert!(capture_desktop_region(&backend, 3200, 0, u32::MAX, 100).is_err());
+        assert!(capture_desktop_region(&backend, 0, 0, 0, 100).is_err());
+    }
+
+    #[test]
+    fn letterbox_bars_the_short_side() {
+        let image = RgbaImage::from_pixel(200, 100, Rgba([255, 255, 255, 255]));
+        let out = letterbox(image, 100, 100).unwrap();
+        assert_eq!(out.dimensions(), (100, 100));
+        assert_eq!(out.get_pixel(50, 10), &Rgba([0, 0, 0, 255]));
+        assert_eq!(out.get_pixel(50, 50), &Rgba([255, 255, 255, 255]));
+        assert_eq!(out.get_pixel(50, 90), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn letterbox_rejects_empty_sizes() {
+        assert!(letterbox(RgbaImage::new(10, 10), 0, 10).is_err());
+        assert!(letterbox(RgbaImage::new(10, 10), 10, 0).is_err());
+        assert!(letterbox(RgbaImage::new(0, 10), 10, 10).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::error::CaptureError;
+use crate::recording::{fit_size, frame_due};
+use image::codecs::qoi::QoiEncoder;
+use image::{imageops, ExtendedColorType, ImageEncoder, ImageFormat, RgbaImage};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ReplayOptions {
+    /// How much history to keep.
+    pub seconds: u32,
+    /// Frames kept per second. Extra captured frames are skipped.
+    pub fps: u32,
+    /// Frames larger than this are scaled down before they are stored.
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Upper bound for the compressed frames. The oldest frames are dropped
+    /// first, so a busy screen may keep less than `seconds`.
+    pub max_memory_mb: u32,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            seconds: 30,
+            fps: 15,
+            max_width: 1920,
+            max_height: 1080,
+            max_memory_mb: 512,
+        }
+    }
+}
+
+impl ReplayOptions {
+    pub fn validate(&self) -> Result<(), CaptureError> {
+        if !(1..=600).contains(&self.seconds) {
+            return Err(CaptureError::invalid("seconds must be between 1 and 600"));
+        }
+        if !(1..=60).contains(&self.fps) {
+            return Err(CaptureError::invalid("fps must be between 1 and 60"));
+        }
+        if self.max_width < 16 || self.max_height < 16 {
+            return Err(CaptureError::invalid("Maximum size must be at least 16x16"));
+        }
+        if !(16..=8192).contains(&self.max_memory_mb) {
+            return Err(CaptureError::invalid(
+                "maxMemoryMb must be between 16 and 8192",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A frame stored as QOI, which is lossless and fast to encode.
+#[derive(Clone)]
+pub struct StoredFrame {
+    pub taken_at: Instant,
+    data: Arc<Vec<u8>>,
+}
+
+impl StoredFrame {
+    /// Scales `image` down to the buffer's size limits and compresses it.
+    /// Slow enough to keep off the capture thread and out of the buffer's
+    /// lock.
+    pub fn compress(
+        image: &RgbaImage,
+        taken_at: Instant,
+        options: &ReplayOptions,
+    ) -> Result<Self, CaptureError> {
+        let (width, height) = fit_size(
+            image.width(),
+            image.height(),
+            options.max_width,
+            options.max_height,
+        );
+        let scaled;
+        let image = if image.dimensions() == (width, height) {
+            image
+        } else {
+            scaled = imageops::resize(image, width, height, imageops::FilterType::Triangle);
+            &scaled
+        };
+        let mut data = Vec::new();
+        QoiEncoder::new(&mut data).write_image(
+            image.as_raw(),
+            width,
+            height,
+            ExtendedColorType::Rgba8,
+        )?;
+        Ok(Self {
+            taken_at,
+            data: Arc::new(data),
+        })
+    }
+
+    pub fn decode(&self) -> Result<RgbaImage, CaptureError> {
+        Ok(image::load_from_memory_with_format(&self.data, ImageFormat::Qoi)?.to_rgba8())
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayStatus {
+    pub frames: usize,
+    pub bytes: usize,
+    /// Time covered by the buffered frames.
+    pub duration_ms: u64,
+}
+
+/// The last few seconds of a stream, kept so they can be saved after the
+/// fact.
+pub struct ReplayBuffer {
+    pub options: ReplayOptions,
+    frames: VecDeque<StoredFrame>,
+    bytes: usize,
+}
+
+impl ReplayBuffer {
+    pub fn new(options: ReplayOptions) -> Result<Self, CaptureError> {
+        options.validate()?;
+        Ok(Self {
+            options,
+            frames: VecDeque::new(),
+            bytes: 0,
+        })
+    }
+
+    /// Whether a frame taken at `taken_at` is wanted, or comes sooner than
+    /// the frame rate allows.
+    pub fn due(&self, taken_at: Instant) -> bool {
+        let interval = Duration::from_secs(1) / self.options.fps;
+        frame_due(
+            self.frames.back().map(|last| last.taken_at),
+            taken_at,
+            interval,
+        )
+    }
+
+    /// Stores a frame from `StoredFrame::compress`, then drops frames that
+    /// are too old or over the memory cap.
+    pub fn push(&mut self, frame: StoredFrame) {
+        let taken_at = frame.taken_at;
+        self.bytes += frame.data.len();
+        self.frames.push_back(frame);
+
+        let max_age = Duration::from_secs(self.options.seconds as u64);
+        let max_bytes = self.options.max_memory_mb as usize * 1024 * 1024;
+        while let Some(oldest) = self.frames.front() {
+            if taken_at.duration_since(oldest.taken_at) <= max_age && self.bytes <= max_bytes {
+                break;
+            }
+            self.bytes -= oldest.data.len();
+            self.frames.pop_front();
+        }
+    }
+
+    /// The buffered frames, oldest first. Cheap: frame data is shared.
+    pub fn snapshot(&self) -> Vec<StoredFrame> {
+        self.frames.iter().cloned().collect()
+    }
+
+    pub fn status(&self) -> ReplayStatus {
+        let duration_ms = match (self.frames.front(), self.frames.back()) {
+            (Some(first), Some(last)) => last.taken_at.duration_since(first.taken_at).as_millis(),
+            _ => 0,
+        };
+        ReplayStatus {
+            frames: self.frames.len(),
+            bytes: self.bytes,
+            duration_ms: duration_ms as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(buffer: &ReplayBuffer, taken_at: Instant) -> StoredFrame {
+        StoredFrame::compress(&RgbaImage::new(64, 48), taken_at, &buffer.options).unwrap()
+    }
+
+    #[test]
+    fn skips_frames_sooner_than_the_frame_rate() {
+        let mut buffer = ReplayBuffer::new(ReplayOptions::default()).unwrap();
+        let start = Instant::now();
+        assert!(buffer.due(start));
+        buffer.push(frame(&buffer, start));
+        assert!(!buffer.due(start + Duration::from_millis(10)));
+        assert!(buffer.due(start + Duration::from_millis(70)));
+    }
+
+    #[test]
+    fn drops_frames_older_than_the_window() {
+        let options = ReplayOptions {
+            seconds: 1,
+            ..Default::default()
+        };
+        let mut buffer = ReplayBuffer::new(options).unwrap();
+        let start = Instant::now();
+        for i in 0..30 {
+            buffer.push(frame(&buffer, start + Duration::from_millis(i * 100)));
+        }
+        let status = buffer.status();
+        assert_eq!(status.frames, 11);
+        assert_eq!(status.duration_ms, 1000);
+        assert_eq!(
+            buffer.snapshot()[0].decode().unwrap().dimensions(),
+            (64, 48)
+        );
+    }
+
+    #[test]
+    fn compress_scales_to_the_limits() {
+        let options = ReplayOptions {
+            max_width: 32,
+            max_height: 32,
+            ..Default::default()
+        };
+        let frame = StoredFrame::compress(&RgbaImage::new(128, 64), Instant::now(), &options);
+        assert_eq!(frame.unwrap().decode().unwrap().dimensions(), (32, 16));
+    }
+}
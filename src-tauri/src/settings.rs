@@ -0,0 +1,213 @@
+use crate::error::CaptureError;
+use crate::files;
+use crate::naming::{self, Collision, FileKind};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+const SETTINGS_FILE: &str = "settings.json";
+
+/// Version written to new settings files. Bump it and append to `MIGRATIONS`
+/// whenever a field is renamed or changes meaning.
+const SCHEMA_VERSION: u32 = 1;
+
+/// Upgrades a settings document by one version; entry `n` takes version `n`
+/// to `n + 1`.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[migrate_v0];
+
+/// Version 0 documents are the webview's old localStorage values copied
+/// verbatim, where every value is a string; see `import_legacy`.
+fn migrate_v0(doc: &mut Map<String, Value>) {
+    if let Some(Value::String(auto_save)) = doc.get("autoSave") {
+        let auto_save = auto_save == "true";
+        doc.insert("autoSave".to_string(), Value::Bool(auto_save));
+    }
+    if doc.get("defaultPath").is_some_and(|p| p == "") {
+        doc.insert("defaultPath".to_string(), Value::Null);
+    }
+}
+
+/// User preferences shared by the webview and the Rust side.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    pub version: u32,
+    /// Folder captures and recordings are saved to.
+    pub default_path: Option<String>,
+    /// Save straight to `default_path` instead of asking for a file.
+    pub auto_save: bool,
+    /// Name of saved captures, without extension; see `naming` for tokens.
+    pub image_template: String,
+    /// Name of saved recordings, without extension.
+    pub video_template: String,
+    pub on_collision: Collision,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            default_path: None,
+            auto_save: false,
+            image_template: "screenshot_{mode}_{date}T{time}".to_string(),
+            video_template: "recording_{date}T{time}".to_string(),
+            on_collision: Collision::Suffix,
+        }
+    }
+}
+
+impl Settings {
+    pub fn template_for(&self, kind: FileKind) -> &str {
+        match kind {
+            FileKind::Image => &self.image_template,
+            FileKind::Video => &self.video_template,
+        }
+    }
+}
+
+/// Resolves `file` inside the app's config directory.
+pub fn config_path(app: &AppHandle, file: &str) -> Result<PathBuf, CaptureError> {
+    let dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|e| CaptureError::invalid(e.to_string()))?;
+    Ok(dir.join(file))
+}
+
+/// Brings a settings document of any older version up to `SCHEMA_VERSION`.
+fn migrate(mut doc: Map<String, Value>) -> Result<Settings, CaptureError> {
+    upgrade(&mut doc)?;
+    serde_json::from_value(Value::Object(doc)).map_err(|e| CaptureError::invalid(e.to_string()))
+}
+
+fn upgrade(doc: &mut Map<String, Value>) -> Result<(), CaptureError> {
+    let mut version = doc.get("version").and_then(Value::as_u64).unwrap_or(0) as usize;
+    if version > SCHEMA_VERSION as usize {
+        return Err(CaptureError::invalid(format!(
+            "Settings version {} is newer than this build supports",
+            version
+        )));
+    }
+    while version < SCHEMA_VERSION as usize {
+        MIGRATIONS[version](doc);
+        version += 1;
+    }
+    doc.insert("version".to_string(), Value::from(SCHEMA_VERSION));
+    Ok(())
+}
+
+/// The current settings and the file they persist to.
+pub struct SettingsStore {
+    path: Option<PathBuf>,
+    settings: Mutex<Settings>,
+}
+
+impl SettingsStore {
+    /// Reads the settings file, migrating it if it was written by an older
+    /// version. Missing or unreadable files fall back to the defaults.
+    pub fn load(app: &AppHandle) -> Self {
+        let path = config_path(app, SETTINGS_FILE).ok();
+        let settings = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok().map(|text| (path, text)))
+            .map(|(path, text)| {
+                let loaded = serde_json::from_str::<Map<String, Value>>(&text)
+                    .map_err(|e| CaptureError::invalid(e.to_string()))
+                    .and_then(migrate);
+                loaded.unwrap_or_else(|e| {
+                    println!("Ignoring invalid settings {}: {}", path.display(), e);
+                    Settings::default()
+                })
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            settings: Mutex::new(settings),
+        }
+    }
+
+    /// A store that is never written to disk.
+    #[cfg(test)]
+    pub fn in_memory(settings: Settings) -> Self {
+        Self {
+            path: None,
+            settings: Mutex::new(settings),
+        }
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Takes over the values the webview used to keep in localStorage, as
+    /// a version 0 document. The default folder is left out: only a folder
+    /// dialog may set it, since it decides where files can be written.
+    pub fn import_legacy(&self, mut values: Map<String, Value>) -> Result<Settings, CaptureError> {
+        values.insert("version".to_string(), Value::from(0));
+        upgrade(&mut values)?;
+        values.remove("defaultPath");
+        self.update(values)
+    }
+
+    /// Applies the fields present in `patch`, persists the result and
+    /// returns it. Unknown fields are rejected so typos do not get silently
+    /// dropped.
+    pub fn update(&self, patch: Map<String, Value>) -> Result<Settings, CaptureError> {
+        let mut settings = self.settings.lock().unwrap();
+        let Value::Object(mut doc) =
+            serde_json::to_value(&*settings).map_err(|e| CaptureError::invalid(e.to_string()))?
+        else {
+            unreachable!("Settings serializes as an object");
+        };
+        for (key, value) in patch {
+            if key == "version" {
+                continue;
+            }
+            if !doc.contains_key(&key) {
+                return Err(CaptureError::invalid(format!("Unknown setting {}", key)));
+            }
+            doc.insert(key, value);
+        }
+        let updated: Settings = serde_json::from_value(Value::Object(doc))
+            .map_err(|e| CaptureError::invalid(e.to_string()))?;
+        naming::validate(&updated.image_template)?;
+        naming::validate(&updated.video_template)?;
+
+        if let Some(path) = &self.path {
+            let text = serde_json::to_string_pretty(&updated)
+                .map_err(|e| CaptureError::invalid(e.to_string()))?;
+            // A crash mid-write must not leave a truncated settings file
+            files::write_atomic(path, text.as_bytes(), true)?;
+        }
+        *settings = updated.clone();
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn doc(value: Value) -> Map<String, Value> {
+        let Value::Object(map) = value else {
+            panic!("not an object")
+        };
+        map
+    }
+
+    #[test]
+    fn migrates_localstorage_values() {
+        let settings = migrate(doc(json!({ "autoSave": "true", "defaultPath": "" }))).unwrap();
+        assert_eq!(settings.version, SCHEMA_VERSION);
+        assert!(settings.auto_save);
+        assert_eq!(settings.default_path, None);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        assert!(migrate(doc(json!({ "version": SCHEMA_VERSION + 1 }))).is_err());
+    }
+}
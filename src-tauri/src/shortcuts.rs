@@ -0,0 +1,263 @@
+use crate::error::CaptureError;
+use crate::files;
+use crate::settings::config_path;
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut};
+
+const KEYMAP_FILE: &str = "keymap.json";
+
+/// Something a global shortcut can trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ShortcutAction {
+    /// Capture the monitor under the cursor and open area selection.
+    AreaCapture,
+    /// Capture the monitor under the cursor.
+    Fullscreen,
+    ActiveWindow,
+    WindowAtCursor,
+    /// Start recording, or stop it if already recording.
+    ToggleRecording,
+    /// Capture the region most recently cropped or captured again.
+    RepeatLastRegion,
+    /// Save what the replay buffer holds.
+    SaveReplay,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Binding {
+    pub action: ShortcutAction,
+    /// Accelerator such as `CommandOrControl+Shift+F11`.
+    pub accelerator: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Keymap {
+    pub bindings: Vec<Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bind = |action, accelerator: &str| Binding {
+            action,
+            accelerator: accelerator.to_string(),
+        };
+        Self {
+            bindings: vec![
+                bind(ShortcutAction::AreaCapture, "Control+Shift+F11"),
+                bind(ShortcutAction::AreaCapture, "Super+Shift+F11"),
+                bind(ShortcutAction::ActiveWindow, "Control+Shift+F10"),
+                bind(ShortcutAction::ActiveWindow, "Super+Shift+F10"),
+                bind(ShortcutAction::WindowAtCursor, "Control+Shift+F9"),
+                bind(ShortcutAction::WindowAtCursor, "Super+Shift+F9"),
+            ],
+        }
+    }
+}
+
+/// A binding that could not be registered.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RegistrationFailure {
+    pub action: ShortcutAction,
+    pub accelerator: String,
+    pub reason: String,
+}
+
+impl RegistrationFailure {
+    fn new(binding: &Binding, reason: String) -> Self {
+        Self {
+            action: binding.action,
+            accelerator: binding.accelerator.clone(),
+            reason,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct KeymapStatus {
+    pub keymap: Keymap,
+    pub failures: Vec<RegistrationFailure>,
+    /// Problems not tied to one binding, such as an unreadable keymap file.
+    pub errors: Vec<String>,
+}
+
+/// The active keymap and which registered shortcut triggers which action.
+#[derive(Default)]
+pub struct KeymapState {
+    keymap: Mutex<Keymap>,
+    actions: Mutex<HashMap<u32, ShortcutAction>>,
+    failures: Mutex<Vec<RegistrationFailure>>,
+    errors: Mutex<Vec<String>>,
+}
+
+impl KeymapState {
+    pub fn action_for(&self, shortcut: &Shortcut) -> Option<ShortcutAction> {
+        self.actions.lock().unwrap().get(&shortcut.id()).copied()
+    }
+
+    pub fn status(&self) -> KeymapStatus {
+        KeymapStatus {
+            keymap: self.keymap.lock().unwrap().clone(),
+            failures: self.failures.lock().unwrap().clone(),
+            errors: self.errors.lock().unwrap().clone(),
+        }
+    }
+
+    /// Adds a problem to report in the status until the next `apply`.
+    pub fn report(&self, error: String) {
+        println!("{}", error);
+        self.errors.lock().unwrap().push(error);
+    }
+}
+
+/// Reads the saved keymap, falling back to the defaults when there is none
+/// or it cannot be parsed. A keymap that cannot be parsed is also described
+/// in the returned error, for the caller to report.
+pub fn load(app: &AppHandle) -> (Keymap, Option<String>) {
+    let Ok(path) = config_path(app, KEYMAP_FILE) else {
+        return (Keymap::default(), None);
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => match serde_json::from_str(&text) {
+            Ok(keymap) => (keymap, None),
+            Err(e) => (
+                Keymap::default(),
+                Some(format!(
+                    "Ignored invalid keymap {}, using the defaults: {}",
+                    path.display(),
+                    e
+                )),
+            ),
+        },
+        Err(_) => (Keymap::default(), None),
+    }
+}
+
+pub fn save(app: &AppHandle, keymap: &Keymap) -> Result<(), CaptureError> {
+    let path = config_path(app, KEYMAP_FILE)?;
+    let text =
+        serde_json::to_string_pretty(keymap).map_err(|e| CaptureError::invalid(e.to_string()))?;
+    files::write_atomic(&path, text.as_bytes(), true)
+}
+
+/// Parses the keymap's accelerators, dropping those that are invalid or
+/// already bound to another action. A binding repeated for the same action
+/// is kept once.
+fn resolve(keymap: &Keymap) -> (Vec<(Shortcut, &Binding)>, Vec<RegistrationFailure>) {
+    let mut shortcuts: Vec<(Shortcut, &Binding)> = Vec::new();
+    let mut failures = Vec::new();
+    for binding in &keymap.bindings {
+        let shortcut = match Shortcut::from_str(&binding.accelerator) {
+            Ok(shortcut) => shortcut,
+            Err(e) => {
+                let reason = format!("Invalid accelerator: {}", e);
+                failures.push(RegistrationFailure::new(binding, reason));
+                continue;
+            }
+        };
+        match shortcuts.iter().find(|(s, _)| s.id() == shortcut.id()) {
+            Some((_, existing)) if existing.action == binding.action => {}
+            Some((_, existing)) => {
+                let reason = format!("Already bound to {:?}", existing.action);
+                failures.push(RegistrationFailure::new(binding, reason));
+            }
+            None => shortcuts.push((shortcut, binding)),
+        }
+    }
+    (shortcuts, failures)
+}
+
+/// Replaces every registered shortcut with the bindings in `keymap`.
+///
+/// Bindings that fail to parse, clash with an earlier binding for another
+/// action, or are refused by the OS (typically because another application
+/// holds them) are skipped and reported.
+pub fn apply(app: &AppHandle, keymap: Keymap) -> Vec<RegistrationFailure> {
+    let state = app.state::<KeymapState>();
+    state.errors.lock().unwrap().clear();
+    let global_shortcut = app.global_shortcut();
+    if let Err(e) = global_shortcut.unregister_all() {
+        state.report(format!("Failed to unregister shortcuts: {}", e));
+    }
+
+    let (shortcuts, mut failures) = resolve(&keymap);
+    let mut actions: HashMap<u32, ShortcutAction> = HashMap::new();
+    for (shortcut, binding) in shortcuts {
+        match global_shortcut.register(shortcut) {
+            Ok(()) => {
+                actions.insert(shortcut.id(), binding.action);
+            }
+            Err(e) => failures.push(RegistrationFailure::new(binding, e.to_string())),
+        }
+    }
+
+    *state.keymap.lock().unwrap() = keymap;
+    *state.actions.lock().unwrap() = actions;
+    *state.failures.lock().unwrap() = failures.clone();
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(bindings: &[(ShortcutAction, &str)]) -> Keymap {
+        Keymap {
+            bindings: bindings
+                .iter()
+                .map(|&(action, accelerator)| Binding {
+                    action,
+                    accelerator: accelerator.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn default_keymap_has_no_conflicts() {
+        let keymap = Keymap::default();
+        let (shortcuts, failures) = resolve(&keymap);
+        assert!(failures.is_empty(), "{failures:?}");
+        assert_eq!(shortcuts.len(), keymap.bindings.len());
+    }
+
+    #[test]
+    fn duplicate_accelerators_keep_the_first_action() {
+        let keymap = keymap(&[
+            (ShortcutAction::Fullscreen, "Control+Shift+F1"),
+            (ShortcutAction::AreaCapture, "ctrl+shift+f1"),
+            (ShortcutAction::Fullscreen, "Shift+Control+F1"),
+        ]);
+        let (shortcuts, failures) = resolve(&keymap);
+        let actions: Vec<_> = shortcuts.iter().map(|(_, b)| b.action).collect();
+        assert_eq!(actions, [ShortcutAction::Fullscreen]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].action, ShortcutAction::AreaCapture);
+        assert_eq!(failures[0].accelerator, "ctrl+shift+f1");
+        assert_eq!(failures[0].reason, "Already bound to Fullscreen");
+    }
+
+    #[test]
+    fn invalid_accelerators_are_reported() {
+        let keymap = keymap(&[
+            (ShortcutAction::Fullscreen, "Control+Nope"),
+            (ShortcutAction::ActiveWindow, ""),
+            (ShortcutAction::SaveReplay, "Alt+F10"),
+        ]);
+        let (shortcuts, failures) = resolve(&keymap);
+        assert_eq!(shortcuts.len(), 1);
+        assert_eq!(shortcuts[0].1.action, ShortcutAction::SaveReplay);
+        let failed: Vec<_> = failures.iter().map(|f| f.action).collect();
+        assert_eq!(
+            failed,
+            [ShortcutAction::Fullscreen, ShortcutAction::ActiveWindow]
+        );
+        assert!(failures
+            .iter()
+            .all(|f| f.reason.starts_with("Invalid accelerator: ")));
+    }
+}
@@ -0,0 +1,256 @@
+use crate::backend::{self, CaptureBackend};
+use crate::error::CaptureError;
+use crate::region;
+use image::RgbaImage;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Oldest captures are dropped once the store holds this many, so a long
+/// session of hotkey captures does not keep every frame in memory.
+const MAX_CAPTURES: usize = 16;
+
+/// What a capture was taken from.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum CaptureSource {
+    Monitor {
+        monitor_id: Option<u32>,
+    },
+    Window {
+        id: u32,
+    },
+    Region {
+        monitor_id: Option<u32>,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    DesktopRegion {
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    },
+    /// The screen area under a window, wherever it is when captured, scaled
+    /// to fit a fixed `width` x `height`. Unlike `Window`, this includes
+    /// anything overlapping the window, and keeps a constant size while the
+    /// window is resized.
+    FollowWindow {
+        id: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Every monitor stitched into one virtual-desktop image.
+    AllMonitors,
+    /// Image bytes sent in by the webview, e.g. an annotated capture.
+    Imported,
+}
+
+impl CaptureSource {
+    /// Checks what can be checked without capturing.
+    pub fn validate(&self) -> Result<(), CaptureError> {
+        if let CaptureSource::FollowWindow { width, height, .. } = *self {
+            if !(16..=8192).contains(&width) || !(16..=8192).contains(&height) {
+                return Err(CaptureError::invalid(
+                    "Output size must be between 16x16 and 8192x8192",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn capture(&self, backend: &dyn CaptureBackend) -> Result<RgbaImage, CaptureError> {
+        match *self {
+            CaptureSource::Monitor { monitor_id } => {
+                let monitor = backend::resolve_monitor(backend, monitor_id)?;
+                backend.capture_monitor(monitor.id)
+            }
+            CaptureSource::Window { id } => backend.capture_window(id),
+            CaptureSource::Region {
+                monitor_id,
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let monitor = backend::resolve_monitor(backend, monitor_id)?;
+                let image = backend.capture_monitor(monitor.id)?;
+                region::crop(image, x, y, width, height)
+            }
+            CaptureSource::DesktopRegion {
+                x,
+                y,
+                width,
+                height,
+            } => region::capture_desktop_region(backend, x, y, width, height),
+            CaptureSource::FollowWindow { id, width, height } => {
+                self.validate()?;
+                let window = backend::find_window(backend, id)?;
+                if window.is_minimized {
+                    return Err(CaptureError::CaptureFailed(format!(
+                        "Window {} is minimized",
+                        id
+                    )));
+                }
+                let image = region::capture_desktop_region(
+                    backend,
+                    window.x,
+                    window.y,
+                    window.width,
+                    window.height,
+                )?;
+                region::letterbox(image, width, height)
+            }
+            CaptureSource::AllMonitors => Ok(region::capture_all_monitors(backend)?.0),
+            CaptureSource::Imported => Err(CaptureError::invalid(
+                "Imported captures cannot be re-taken",
+            )),
+        }
+    }
+
+    /// The source that re-takes a crop of this capture, for sources where the
+    /// crop maps back to a fixed area of one monitor.
+    pub fn cropped(&self, x: u32, y: u32, width: u32, height: u32) -> Option<CaptureSource> {
+        match *self {
+            CaptureSource::Monitor { monitor_id } => Some(CaptureSource::Region {
+                monitor_id,
+                x,
+                y,
+                width,
+                height,
+            }),
+            CaptureSource::Region {
+                monitor_id,
+                x: left,
+                y: top,
+                ..
+            } => Some(CaptureSource::Region {
+                monitor_id,
+                x: left + x,
+                y: top + y,
+                width,
+                height,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Metadata returned to the webview in place of pixels.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CaptureMeta {
+    pub id: u64,
+    pub width: u32,
+    pub height: u32,
+    pub source: CaptureSource,
+    /// The capture this one was cropped from, if any.
+    pub parent: Option<u64>,
+    /// Milliseconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+struct StoredCapture {
+    meta: CaptureMeta,
+    image: Arc<RgbaImage>,
+}
+
+/// Decoded captures kept on the Rust side and referenced by ID.
+#[derive(Default)]
+pub struct CaptureStore {
+    next_id: u64,
+    captures: BTreeMap<u64, StoredCapture>,
+}
+
+impl CaptureStore {
+    /// Stores a capture and returns its metadata along with the IDs of any
+    /// old captures dropped to make room.
+    pub fn insert(
+        &mut self,
+        image: RgbaImage,
+        source: CaptureSource,
+        parent: Option<u64>,
+    ) -> (CaptureMeta, Vec<u64>) {
+        self.next_id += 1;
+        let meta = CaptureMeta {
+            id: self.next_id,
+            width: image.width(),
+            height: image.height(),
+            source,
+            parent,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+        };
+        self.captures.insert(
+            meta.id,
+            StoredCapture {
+                meta: meta.clone(),
+                image: Arc::new(image),
+            },
+        );
+        let mut evicted = Vec::new();
+        while self.captures.len() > MAX_CAPTURES {
+            if let Some((id, _)) = self.captures.pop_first() {
+                evicted.push(id);
+            }
+        }
+        (meta, evicted)
+    }
+
+    /// Returns the capture's metadata and a shared handle to its pixels, so
+    /// callers can encode without holding the store lock.
+    pub fn get(&self, id: u64) -> Result<(CaptureMeta, Arc<RgbaImage>), CaptureError> {
+        self.captures
+            .get(&id)
+            .map(|c| (c.meta.clone(), c.image.clone()))
+            .ok_or(CaptureError::CaptureNotFound { id })
+    }
+
+    pub fn remove(&mut self, id: u64) -> Result<(), CaptureError> {
+        self.captures
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(CaptureError::CaptureNotFound { id })
+    }
+
+    pub fn list(&self) -> Vec<CaptureMeta> {
+        self.captures.values().map(|c| c.meta.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SyntheticBackend;
+
+    #[test]
+    fn follow_window_letterboxes() {
+        let source = CaptureSource::FollowWindow {
+            id: 101,
+            width: 400,
+            height: 400,
+        };
+        let image = source.capture(&SyntheticBackend::default()).unwrap();
+        assert_eq!(image.dimensions(), (400, 400));
+    }
+
+    #[test]
+    fn follow_window_rejects_bad_sizes() {
+        for (width, height) in [(0, 400), (400, 0), (8, 400), (400, 9000)] {
+            let source = CaptureSource::FollowWindow {
+                id: 101,
+                width,
+                height,
+            };
+            let error = source.capture(&SyntheticBackend::default()).unwrap_err();
+            assert_eq!(error.code(), "invalid_input");
+        }
+    }
+}
@@ -0,0 +1,521 @@
+use crate::backend::{self, CaptureBackend};
+use crate::error::CaptureError;
+use crate::store::CaptureSource;
+use image::codecs::jpeg::JpegEncoder;
+use image::{imageops, DynamicImage, ExtendedColorType, RgbaImage};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How far back `StreamStats` rates look.
+const STATS_WINDOW: Duration = Duration::from_secs(2);
+
+/// What a stream's frames are for.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamPurpose {
+    /// Frames shown in the webview.
+    View,
+    /// Frames fed to the recording, with previews shown in the webview.
+    Recording,
+    /// Frames kept in the replay buffer and not shown.
+    Replay,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamInfo {
+    pub id: u64,
+    pub source: CaptureSource,
+    pub fps: u32,
+    pub purpose: StreamPurpose,
+}
+
+/// What a stream's threads share with the registry.
+#[derive(Clone)]
+pub struct StreamHandle {
+    /// Cleared to stop the stream.
+    pub running: Arc<AtomicBool>,
+    pub meter: Arc<StreamMeter>,
+}
+
+struct StreamEntry {
+    info: StreamInfo,
+    handle: StreamHandle,
+}
+
+/// Running capture streams, referenced by ID. Each stream runs its own
+/// capture thread, which removes its entry when it exits.
+#[derive(Default)]
+pub struct StreamRegistry {
+    next_id: u64,
+    streams: BTreeMap<u64, StreamEntry>,
+}
+
+impl StreamRegistry {
+    /// Registers a stream and returns its info and the handle its threads
+    /// run with.
+    pub fn add(
+        &mut self,
+        source: CaptureSource,
+        fps: u32,
+        purpose: StreamPurpose,
+    ) -> Result<(StreamInfo, StreamHandle), CaptureError> {
+        if !(1..=60).contains(&fps) {
+            return Err(CaptureError::invalid("fps must be between 1 and 60"));
+        }
+        if matches!(source, CaptureSource::Imported) {
+            return Err(CaptureError::invalid(
+                "Imported captures cannot be streamed",
+            ));
+        }
+        self.next_id += 1;
+        let info = StreamInfo {
+            id: self.next_id,
+            source,
+            fps,
+            purpose,
+        };
+        let handle = StreamHandle {
+            running: Arc::new(AtomicBool::new(true)),
+            meter: Arc::new(StreamMeter::new(fps)),
+        };
+        self.streams.insert(
+            info.id,
+            StreamEntry {
+                info: info.clone(),
+                handle: handle.clone(),
+            },
+        );
+        Ok((info, handle))
+    }
+
+    pub fn list(&self) -> Vec<StreamInfo> {
+        self.streams.values().map(|s| s.info.clone()).collect()
+    }
+
+    fn get(&self, id: u64) -> Result<&StreamEntry, CaptureError> {
+        self.streams
+            .get(&id)
+            .ok_or(CaptureError::StreamNotFound { id })
+    }
+
+    /// Asks a stream's thread to stop after its current frame.
+    pub fn stop(&self, id: u64) -> Result<(), CaptureError> {
+        self.get(id)?.handle.running.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn stats(&self, id: u64) -> Result<StreamStats, CaptureError> {
+        let entry = self.get(id)?;
+        Ok(entry.handle.meter.stats(id))
+    }
+
+    /// Asks every stream with `purpose` to stop.
+    pub fn stop_all(&self, purpose: StreamPurpose) {
+        for entry in self.streams.values() {
+            if entry.info.purpose == purpose {
+                entry.handle.running.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Called by a stream's thread as it exits.
+    pub fn remove(&mut self, id: u64) {
+        self.streams.remove(&id);
+    }
+}
+
+/// `source` with its monitor resolved, so a stream started on the primary
+/// monitor stays on it. A missing monitor or window is reported up front.
+pub fn pin_source(
+    backend: &dyn CaptureBackend,
+    source: CaptureSource,
+) -> Result<CaptureSource, CaptureError> {
+    source.validate()?;
+    Ok(match source {
+        CaptureSource::Monitor { monitor_id } => CaptureSource::Monitor {
+            monitor_id: Some(backend::resolve_monitor(backend, monitor_id)?.id),
+        },
+        CaptureSource::Region {
+            monitor_id,
+            x,
+            y,
+            width,
+            height,
+        } => CaptureSource::Region {
+            monitor_id: Some(backend::resolve_monitor(backend, monitor_id)?.id),
+            x,
+            y,
+            width,
+            height,
+        },
+        CaptureSource::FollowWindow { id, .. } | CaptureSource::Window { id } => {
+            backend::find_window(backend, id)?;
+            source
+        }
+        source => source,
+    })
+}
+
+/// Calls `capture` until `running` is cleared, aiming for one frame per
+/// `interval`, and hands each frame to `on_frame` with the time it was
+/// taken. Frames are paced against a schedule rather than sleeping a fixed
+/// time, so a slow frame shortens the wait for the next one; when capture
+/// falls a whole frame behind, the schedule restarts from now instead of
+/// bursting to catch up.
+///
+/// Capture times and errors go to `meter`. Errors skip the frame and are
+/// passed to `on_error` once until a different one comes up, except that a
+/// closed window ends the loop.
+pub fn capture_loop(
+    running: &AtomicBool,
+    interval: Duration,
+    meter: &StreamMeter,
+    mut capture: impl FnMut() -> Result<RgbaImage, CaptureError>,
+    mut on_frame: impl FnMut(RgbaImage, Instant),
+    mut on_error: impl FnMut(&CaptureError),
+) {
+    let mut next_frame = Instant::now();
+    let mut errors = RepeatFilter::default();
+    while running.load(Ordering::SeqCst) {
+        let start = Instant::now();
+        match capture() {
+            Ok(image) => {
+                meter.captured(start.elapsed());
+                errors.clear();
+                on_frame(image, start);
+            }
+            Err(e) => {
+                meter.failed(&e);
+                if errors.is_new(&e) {
+                    on_error(&e);
+                }
+                if matches!(e, CaptureError::WindowNotFound { .. }) {
+                    break;
+                }
+            }
+        }
+
+        next_frame += interval;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+}
+
+/// Lets through errors that differ from the one before, so a failure that
+/// repeats every frame is reported once.
+#[derive(Default)]
+pub struct RepeatFilter {
+    last: Option<String>,
+}
+
+impl RepeatFilter {
+    pub fn is_new(&mut self, error: &CaptureError) -> bool {
+        let message = error.to_string();
+        let new = self.last.as_ref() != Some(&message);
+        self.last = Some(message);
+        new
+    }
+
+    /// Called after a success, so the next failure is reported even if it
+    /// repeats the last one.
+    pub fn clear(&mut self) {
+        self.last = None;
+    }
+}
+
+/// A bounded queue between a stream's capture and encode threads. When it
+/// is full the oldest frame is dropped, so a consumer that falls behind
+/// always catches up on the most recent frames.
+pub struct FrameQueue<T> {
+    state: Mutex<QueueState<T>>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+impl<T> FrameQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                items: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
+            ready: Condvar::new(),
+            capacity,
+        }
+    }
+
+    /// Adds an item, returning true if the oldest one was dropped for it.
+    pub fn push(&self, item: T) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let dropped = state.items.len() >= self.capacity;
+        if dropped {
+            state.items.pop_front();
+        }
+        state.items.push_back(item);
+        self.ready.notify_one();
+        dropped
+    }
+
+    /// Waits for the next item. Returns `None` once the queue is closed and
+    /// empty.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// How a stream is keeping up, as returned by `get_stream_stats`. Rates and
+/// averages cover the last two seconds; counts cover the whole stream.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamStats {
+    pub id: u64,
+    pub target_fps: u32,
+    /// Frames captured per second.
+    pub capture_fps: f64,
+    /// Frames encoded and sent to the webview per second. Zero for streams
+    /// that show nothing, like the replay buffer's.
+    pub output_fps: f64,
+    /// Average time to capture a frame.
+    pub capture_ms: f64,
+    /// Average time to encode a frame for the webview.
+    pub encode_ms: f64,
+    pub bytes_per_sec: f64,
+    /// Frames sent to the webview.
+    pub frames: u64,
+    /// Frames dropped because encoding or the webview fell behind.
+    pub dropped: u64,
+    /// Frames that failed to capture or encode.
+    pub errors: u64,
+    /// The most recent failure, if any.
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct Samples {
+    /// When each recent frame was captured and how long it took.
+    captures: VecDeque<(Instant, Duration)>,
+    /// When each recent frame was sent, its encode time and size.
+    sends: VecDeque<(Instant, Duration, usize)>,
+    frames: u64,
+    dropped: u64,
+    errors: u64,
+    last_error: Option<String>,
+}
+
+impl Samples {
+    fn prune(&mut self, now: Instant) {
+        let recent = |at: &Instant| now.duration_since(*at) <= STATS_WINDOW;
+        while self.captures.front().is_some_and(|(at, _)| !recent(at)) {
+            self.captures.pop_front();
+        }
+        while self.sends.front().is_some_and(|(at, _, _)| !recent(at)) {
+            self.sends.pop_front();
+        }
+    }
+}
+
+/// Collects a stream's timings and counts from its capture and encode
+/// threads.
+pub struct StreamMeter {
+    target_fps: u32,
+    started: Instant,
+    samples: Mutex<Samples>,
+}
+
+impl StreamMeter {
+    pub fn new(target_fps: u32) -> Self {
+        Self {
+            target_fps,
+            started: Instant::now(),
+            samples: Mutex::new(Samples::default()),
+        }
+    }
+
+    pub fn captured(&self, took: Duration) {
+        let now = Instant::now();
+        let mut samples = self.samples.lock().unwrap();
+        samples.captures.push_back((now, took));
+        samples.prune(now);
+    }
+
+    pub fn failed(&self, error: &CaptureError) {
+        let mut samples = self.samples.lock().unwrap();
+        samples.errors += 1;
+        samples.last_error = Some(error.to_string());
+    }
+
+    pub fn dropped(&self) {
+        self.samples.lock().unwrap().dropped += 1;
+    }
+
+    pub fn sent(&self, bytes: usize, encode_time: Duration) {
+        let now = Instant::now();
+        let mut samples = self.samples.lock().unwrap();
+        samples.sends.push_back((now, encode_time, bytes));
+        samples.frames += 1;
+        samples.prune(now);
+    }
+
+    pub fn stats(&self, id: u64) -> StreamStats {
+        let now = Instant::now();
+        let mut samples = self.samples.lock().unwrap();
+        samples.prune(now);
+        // A young stream has not filled the window yet
+        let span = now
+            .duration_since(self.started)
+            .min(STATS_WINDOW)
+            .as_secs_f64()
+            .max(0.001);
+        let average_ms = |total: Duration, count: usize| match count {
+            0 => 0.0,
+            count => total.as_secs_f64() * 1000.0 / count as f64,
+        };
+        let capture_time = samples.captures.iter().map(|(_, took)| *took).sum();
+        let encode_time = samples.sends.iter().map(|(_, took, _)| *took).sum();
+        let bytes: usize = samples.sends.iter().map(|(_, _, bytes)| bytes).sum();
+        StreamStats {
+            id,
+            target_fps: self.target_fps,
+            capture_fps: samples.captures.len() as f64 / span,
+            output_fps: samples.sends.len() as f64 / span,
+            capture_ms: average_ms(capture_time, samples.captures.len()),
+            encode_ms: average_ms(encode_time, samples.sends.len()),
+            bytes_per_sec: bytes as f64 / span,
+            frames: samples.frames,
+            dropped: samples.dropped,
+            errors: samples.errors,
+            last_error: samples.last_error.clone(),
+        }
+    }
+}
+
+/// Encodes a frame as RGB JPEG (RGBA JPEG shows up gray in browsers),
+/// first shrinking it to fit `max_width` if given.
+pub fn encode_jpeg(
+    image: RgbaImage,
+    quality: u8,
+    max_width: Option<u32>,
+) -> Result<Vec<u8>, CaptureError> {
+    let image = match max_width {
+        Some(max_width) if image.width() > max_width => {
+            let height = (image.height() as u64 * max_width as u64 / image.width() as u64).max(1);
+            imageops::thumbnail(&image, max_width, height as u32)
+        }
+        _ => image,
+    };
+    let rgb_image = DynamicImage::ImageRgba8(image).to_rgb8();
+    let mut buffer = Vec::new();
+    JpegEncoder::new_with_quality(&mut buffer, quality).encode(
+        &rgb_image,
+        rgb_image.width(),
+        rgb_image.height(),
+        ExtendedColorType::Rgb8,
+    )?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_drops_the_oldest_frame() {
+        let queue = FrameQueue::new(2);
+        assert!(!queue.push(1));
+        assert!(!queue.push(2));
+        assert!(queue.push(3));
+        assert_eq!(queue.pop(), Some(2));
+        queue.close();
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn queue_wakes_a_waiting_consumer() {
+        let queue = Arc::new(FrameQueue::new(2));
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || std::iter::from_fn(|| queue.pop()).collect::<Vec<u32>>())
+        };
+        queue.push(1);
+        thread::sleep(Duration::from_millis(20));
+        queue.push(2);
+        queue.close();
+        assert_eq!(consumer.join().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn registry_stops_by_purpose() {
+        let mut registry = StreamRegistry::default();
+        let source = CaptureSource::Monitor { monitor_id: None };
+        let (view, view_handle) = registry
+            .add(source.clone(), 30, StreamPurpose::View)
+            .unwrap();
+        let (_, replay_handle) = registry
+            .add(source.clone(), 15, StreamPurpose::Replay)
+            .unwrap();
+        assert!(registry.add(source, 0, StreamPurpose::View).is_err());
+        assert!(registry
+            .add(CaptureSource::Imported, 30, StreamPurpose::View)
+            .is_err());
+
+        registry.stop_all(StreamPurpose::View);
+        assert!(!view_handle.running.load(Ordering::SeqCst));
+        assert!(replay_handle.running.load(Ordering::SeqCst));
+
+        registry.remove(view.id);
+        assert_eq!(registry.list().len(), 1);
+        assert_eq!(
+            registry.stop(view.id).unwrap_err().code(),
+            "stream_not_found"
+        );
+    }
+
+    #[test]
+    fn repeat_filter_reports_each_error_once() {
+        let mut errors = RepeatFilter::default();
+        let closed = CaptureError::WindowNotFound { id: 1 };
+        assert!(errors.is_new(&closed));
+        assert!(!errors.is_new(&closed));
+        assert!(errors.is_new(&CaptureError::invalid("other")));
+        errors.clear();
+        assert!(errors.is_new(&CaptureError::invalid("other")));
+    }
+
+    #[test]
+    fn meter_keeps_the_last_error() {
+        let meter = StreamMeter::new(30);
+        meter.failed(&CaptureError::invalid("first"));
+        meter.failed(&CaptureError::invalid("second"));
+        let stats = meter.stats(7);
+        assert_eq!(stats.errors, 2);
+        assert_eq!(stats.last_error.as_deref(), Some("Invalid input: second"));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::backend::{CaptureBackend, WindowInfo};
+use crate::error::CaptureError;
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::{imageops, RgbaImage};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A cached thumbnail is reused for this long as long as the window's title
+/// and geometry are unchanged.
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// A window thumbnail's metadata. The PNG itself is served over the
+/// `capture` protocol as `thumbnail-<window id>`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WindowThumbnail {
+    pub width: u32,
+    pub height: u32,
+    /// Changes whenever the thumbnail is re-taken, so the webview can tell
+    /// when its cached image is out of date.
+    pub version: u64,
+    #[serde(skip)]
+    pub png: Arc<Vec<u8>>,
+}
+
+/// What must stay the same for a cached thumbnail to be reused.
+#[derive(Debug, Clone, PartialEq)]
+struct CacheKey {
+    title: String,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    max_size: u32,
+}
+
+impl CacheKey {
+    fn new(window: &WindowInfo, max_size: u32) -> Self {
+        Self {
+            title: window.title.clone(),
+            x: window.x,
+            y: window.y,
+            width: window.width,
+            height: window.height,
+            max_size,
+        }
+    }
+}
+
+struct CacheEntry {
+    key: CacheKey,
+    taken_at: Instant,
+    thumbnail: WindowThumbnail,
+}
+
+/// A window whose thumbnail could not be taken.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThumbnailFailure {
+    pub window_id: u32,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WindowThumbnails {
+    /// Thumbnails keyed by window ID.
+    pub thumbnails: BTreeMap<u32, WindowThumbnail>,
+    pub failures: Vec<ThumbnailFailure>,
+}
+
+#[derive(Default)]
+pub struct ThumbnailCache {
+    entries: HashMap<u32, CacheEntry>,
+    next_version: u64,
+}
+
+/// Returns a thumbnail for every window, capturing only those whose cached
+/// thumbnail is missing, stale or no longer matches the window. The cache
+/// is only locked to look entries up and store them, never while capturing.
+pub fn thumbnails(
+    cache: &Mutex<ThumbnailCache>,
+    backend: &dyn CaptureBackend,
+    windows: &[WindowInfo],
+    max_size: u32,
+) -> WindowThumbnails {
+    let now = Instant::now();
+    let stale = cache.lock().unwrap().stale(windows, max_size, now);
+    let ids: Vec<u32> = stale.iter().map(|w| w.id).collect();
+    let fresh = downscale_all(backend.capture_windows(&ids), max_size);
+    let mut cache = cache.lock().unwrap();
+    let failures = cache.store(stale.into_iter().zip(fresh), max_size, now);
+    cache.retain(windows);
+    WindowThumbnails {
+        thumbnails: cache
+            .entries
+            .iter()
+            .map(|(id, entry)| (*id, entry.thumbnail.clone()))
+            .collect(),
+        failures,
+    }
+}
+
+impl ThumbnailCache {
+    /// The windows that need a new thumbnail.
+    fn stale(&self, windows: &[WindowInfo], max_size: u32, now: Instant) -> Vec<WindowInfo> {
+        windows
+            .iter()
+            .filter(|w| match self.entries.get(&w.id) {
+                Some(entry) => {
+                    entry.key != CacheKey::new(w, max_size)
+                        || now.duration_since(entry.taken_at) > CACHE_TTL
+                }
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Caches freshly taken thumbnails and returns the windows that failed.
+    fn store(
+        &mut self,
+        taken: impl Iterator<Item = (WindowInfo, Result<WindowThumbnail, CaptureError>)>,
+        max_size: u32,
+        taken_at: Instant,
+    ) -> Vec<ThumbnailFailure> {
+        let mut failures = Vec::new();
+        for (window, thumbnail) in taken {
+            match thumbnail {
+                Ok(mut thumbnail) => {
+                    self.next_version += 1;
+                    thumbnail.version = self.next_version;
+                    self.entries.insert(
+                        window.id,
+                        CacheEntry {
+                            key: CacheKey::new(&window, max_size),
+                            taken_at,
+                            thumbnail,
+                        },
+                    );
+                }
+                Err(e) => {
+                    self.entries.remove(&window.id);
+                    failures.push(ThumbnailFailure {
+                        window_id: window.id,
+                        code: e.code(),
+                        message: e.to_string(),
+                    });
+                }
+            }
+        }
+        failures
+    }
+
+    /// Forgets windows that no longer exist.
+    fn retain(&mut self, windows: &[WindowInfo]) {
+        self.entries
+            .retain(|id, _| windows.iter().any(|w| w.id == *id));
+    }
+
+    /// The PNG of a window's cached thumbnail.
+    pub fn png(&self, id: u32) -> Option<Arc<Vec<u8>>> {
+        self.entries.get(&id).map(|e| e.thumbnail.png.clone())
+    }
+}
+
+/// Downscales and encodes the captures on as many threads as there are
+/// cores, preserving order.
+fn downscale_all(
+    captures: Vec<Result<RgbaImage, CaptureError>>,
+    max_size: u32,
+) -> Vec<Result<WindowThumbnail, CaptureError>> {
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4);
+    let chunk_size = captures.len().div_ceil(workers).max(1);
+
+    let mut results = Vec::with_capacity(captures.len());
+    let mut captures = captures.into_iter();
+    thread::scope(|scope| {
+        let mut handles = Vec::new();
+        loop {
+            let chunk: Vec<_> = captures.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            handles.push(scope.spawn(move || {
+                chunk
+                    .into_iter()
+                    .map(|capture| downscale(&capture?, max_size))
+                    .collect::<Vec<_>>()
+            }));
+        }
+        for handle in handles {
+            results.extend(handle.join().expect("thumbnail worker panicked"));
+        }
+    });
+    results
+}
+
+fn downscale(image: &RgbaImage, max_size: u32) -> Result<WindowThumbnail, CaptureError> {
+    let max_size = max_size.max(1);
+    let (width, height) = image.dimensions();
+    let scale = (max_size as f64 / width.max(height).max(1) as f64).min(1.0);
+    let target_width = ((width as f64 * scale).round() as u32).max(1);
+    let target_height = ((height as f64 * scale).round() as u32).max(1);
+    // `thumbnail` is a fast area-averaging resize, good enough for previews
+    let small = imageops::thumbnail(image, target_width, target_height);
+
+    let mut buffer = Vec::new();
+    let encoder =
+        PngEncoder::new_with_quality(&mut buffer, CompressionType::Fast, PngFilterType::Sub);
+    small.write_with_encoder(encoder)?;
+    Ok(WindowThumbnail {
+        width: target_width,
+        height: target_height,
+        version: 0,
+        png: Arc::new(buffer),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SyntheticBackend;
+
+    fn versions(result: &WindowThumbnails) -> Vec<(u32, u64)> {
+        result
+            .thumbnails
+            .iter()
+            .map(|(id, t)| (*id, t.version))
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_windows_reuse_their_thumbnail() {
+        let backend = SyntheticBackend::default();
+        let cache = Mutex::new(ThumbnailCache::default());
+        let windows = &backend.windows[..3];
+
+        let first = thumbnails(&cache, &backend, windows, 100);
+        assert_eq!(versions(&first), [(101, 1), (102, 2), (103, 3)]);
+        assert_eq!(
+            (first.thumbnails[&101].width, first.thumbnails[&101].height),
+            (100, 75)
+        );
+        let second = thumbnails(&cache, &backend, windows, 100);
+        assert_eq!(versions(&second), versions(&first));
+        assert!(second.failures.is_empty());
+    }
+
+    #[test]
+    fn changed_windows_are_retaken() {
+        let backend = SyntheticBackend::default();
+        let cache = Mutex::new(ThumbnailCache::default());
+        let mut windows = backend.windows[..2].to_vec();
+        thumbnails(&cache, &backend, &windows, 100);
+
+        windows[0].title = "Renamed".to_string();
+        let result = thumbnails(&cache, &backend, &windows, 100);
+        assert_eq!(versions(&result), [(101, 3), (102, 2)]);
+
+        windows[1].x += 10;
+        let result = thumbnails(&cache, &backend, &windows, 100);
+        assert_eq!(versions(&result), [(101, 3), (102, 4)]);
+
+        let result = thumbnails(&cache, &backend, &windows, 50);
+        assert_eq!(versions(&result), [(101, 5), (102, 6)]);
+    }
+
+    #[test]
+    fn expired_thumbnails_are_retaken() {
+        let backend = SyntheticBackend::default();
+        let cache = Mutex::new(ThumbnailCache::default());
+        let windows = &backend.windows[..1];
+        thumbnails(&cache, &backend, windows, 100);
+
+        let later = Instant::now() + CACHE_TTL + Duration::from_secs(1);
+        let stale = cache.lock().unwrap().stale(windows, 100, later);
+        assert_eq!(stale.len(), 1);
+        assert!(cache
+            .lock()
+            .unwrap()
+            .stale(windows, 100, Instant::now())
+            .is_empty());
+    }
+
+    #[test]
+    fn closed_windows_are_forgotten() {
+        let backend = SyntheticBackend::default();
+        let cache = Mutex::new(ThumbnailCache::default());
+        thumbnails(&cache, &backend, &backend.windows[..2], 100);
+        assert!(cache.lock().unwrap().png(102).is_some());
+
+        let result = thumbnails(&cache, &backend, &backend.windows[..1], 100);
+        assert_eq!(versions(&result), [(101, 1)]);
+        assert!(cache.lock().unwrap().png(102).is_none());
+    }
+
+    #[test]
+    fn failed_windows_are_reported() {
+        let backend = SyntheticBackend::default();
+        let cache = Mutex::new(ThumbnailCache::default());
+        let mut windows = backend.windows[..1].to_vec();
+        let mut gone = windows[0].clone();
+        gone.id = 999;
+        windows.push(gone);
+
+        let result = thumbnails(&cache, &backend, &windows, 100);
+        assert_eq!(versions(&result), [(101, 1)]);
+        assert_eq!(result.failures.len(), 1);
+        assert_eq!(result.failures[0].window_id, 999);
+        assert_eq!(result.failures[0].code, "window_not_found");
+    }
+}
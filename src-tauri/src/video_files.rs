@@ -0,0 +1,143 @@
+use crate::error::CaptureError;
+use crate::files;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+struct VideoFile {
+    path: PathBuf,
+    /// `None` once finished, while the file waits to be moved.
+    file: Option<File>,
+    bytes: u64,
+}
+
+/// Recordings being written chunk by chunk as the webview produces them.
+///
+/// Each session appends straight to a `.part` file, so memory use stays flat
+/// and the data written so far survives a crash. The file is moved to its
+/// destination once the recording is finalized. A session stays around
+/// until the move succeeds, so a failed move can be retried or aborted.
+#[derive(Default)]
+pub struct VideoFiles {
+    next_id: u64,
+    open: HashMap<u64, VideoFile>,
+}
+
+impl VideoFiles {
+    /// Starts a session writing to a new file in `dir`. The extension must be
+    /// 1-8 ASCII letters or digits, so it cannot lead out of `dir`.
+    pub fn open(&mut self, dir: &Path, extension: &str) -> Result<u64, CaptureError> {
+        let extension = files::check_extension(extension)?;
+        fs::create_dir_all(dir).map_err(|e| CaptureError::io(dir, e))?;
+        self.next_id += 1;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let path = dir.join(format!(
+            "recording-{}-{}.{}.part",
+            started, self.next_id, extension
+        ));
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| CaptureError::io(&path, e))?;
+        self.open.insert(
+            self.next_id,
+            VideoFile {
+                path,
+                file: Some(file),
+                bytes: 0,
+            },
+        );
+        Ok(self.next_id)
+    }
+
+    /// Appends `data` and returns the total written so far.
+    pub fn append(&mut self, id: u64, data: &[u8]) -> Result<u64, CaptureError> {
+        let video = self
+            .open
+            .get_mut(&id)
+            .ok_or(CaptureError::SessionNotFound { id })?;
+        let file = video
+            .file
+            .as_mut()
+            .ok_or_else(|| CaptureError::invalid(format!("Recording file {} is finished", id)))?;
+        file.write_all(data)
+            .map_err(|e| CaptureError::io(&video.path, e))?;
+        video.bytes += data.len() as u64;
+        Ok(video.bytes)
+    }
+
+    /// Flushes and closes the `.part` file and returns it with its size. The
+    /// session is kept until `remove` or `abort`; finishing it again returns
+    /// the same file.
+    pub fn finish(&mut self, id: u64) -> Result<(PathBuf, u64), CaptureError> {
+        let video = self
+            .open
+            .get_mut(&id)
+            .ok_or(CaptureError::SessionNotFound { id })?;
+        if let Some(file) = video.file.take() {
+            file.sync_all()
+                .map_err(|e| CaptureError::io(&video.path, e))?;
+        }
+        Ok((video.path.clone(), video.bytes))
+    }
+
+    /// Forgets a finished session whose file has been moved away.
+    pub fn remove(&mut self, id: u64) {
+        self.open.remove(&id);
+    }
+
+    /// Ends the session and deletes what was written.
+    pub fn abort(&mut self, id: u64) -> Result<(), CaptureError> {
+        let video = self
+            .open
+            .remove(&id)
+            .ok_or(CaptureError::SessionNotFound { id })?;
+        drop(video.file);
+        fs::remove_file(&video.path).map_err(|e| CaptureError::io(&video.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unsafe_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = VideoFiles::default();
+        for extension in ["", "../webm", "we/bm", "web.m", "toolongext", "\\x"] {
+            assert!(
+                files.open(dir.path(), extension).is_err(),
+                "{:?}",
+                extension
+            );
+        }
+        assert!(files.open(dir.path(), ".webm").is_ok());
+        assert!(files.open(dir.path(), "gif").is_ok());
+    }
+
+    #[test]
+    fn finished_session_can_still_be_aborted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = VideoFiles::default();
+        let id = files.open(dir.path(), "webm").unwrap();
+        files.append(id, b"data").unwrap();
+        let (path, bytes) = files.finish(id).unwrap();
+        assert_eq!(bytes, 4);
+        assert!(files.append(id, b"more").is_err());
+        assert_eq!(files.finish(id).unwrap(), (path.clone(), 4));
+
+        files.abort(id).unwrap();
+        assert!(!path.exists());
+        assert!(matches!(
+            files.finish(id),
+            Err(CaptureError::SessionNotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+/// Size marker for elements whose length is not known up front.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+/// Block timecodes are signed 16-bit offsets from the cluster, in ms.
+const MAX_CLUSTER_SPAN_MS: u64 = 30_000;
+
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    // Lengths of 2^(7n) - 1 are reserved for "unknown", so stay below them
+    let len = (1..=8).find(|n| size < (1u64 << (7 * n)) - 1).unwrap_or(8);
+    let marked = size | (1u64 << (7 * len));
+    out.extend_from_slice(&marked.to_be_bytes()[8 - len as usize..]);
+}
+
+fn element(out: &mut Vec<u8>, id: u32, body: &[u8]) {
+    write_id(out, id);
+    write_size(out, body.len() as u64);
+    out.extend_from_slice(body);
+}
+
+fn uint_element(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    element(out, id, &bytes[skip..]);
+}
+
+/// Splits an AV1 temporal unit into its OBUs as `(type, bytes)`.
+fn obus(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut result = Vec::new();
+    while let Some(&header) = data.first() {
+        let obu_type = (header >> 3) & 0x0F;
+        let has_extension = header & 0x04 != 0;
+        let has_size = header & 0x02 != 0;
+        let mut offset = 1 + has_extension as usize;
+        let size = if has_size {
+            let mut size = 0usize;
+            let mut shift = 0;
+            loop {
+                let Some(&byte) = data.get(offset) else {
+                    return result;
+                };
+                offset += 1;
+                size |= ((byte & 0x7F) as usize) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 || shift >= 56 {
+                    break;
+                }
+            }
+            size
+        } else {
+            data.len().saturating_sub(offset)
+        };
+        let end = (offset + size).min(data.len());
+        result.push((obu_type, &data[..end]));
+        data = &data[end..];
+    }
+    result
+}
+
+/// Minimal append-only WebM muxer for a single AV1 video track.
+///
+/// The segment and clusters are written with unknown sizes, so output can be
+/// appended to a file as it is produced and everything up to the last
+/// complete frame stays playable if recording stops abruptly. No cues are
+/// written, which only makes seeking slower.
+pub struct WebmWriter {
+    width: u32,
+    height: u32,
+    /// The 4-byte AV1 codec configuration record, without config OBUs.
+    av1_config: Vec<u8>,
+    header_written: bool,
+    cluster_start: Option<u64>,
+}
+
+impl WebmWriter {
+    pub fn new(width: u32, height: u32, av1_config: Vec<u8>) -> Self {
+        Self {
+            width,
+            height,
+            av1_config,
+            header_written: false,
+            cluster_start: None,
+        }
+    }
+
+    fn header(&self, sequence_header: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        let mut ebml = Vec::new();
+        uint_element(&mut ebml, EBML_VERSION, 1);
+        uint_element(&mut ebml, EBML_READ_VERSION, 1);
+        uint_element(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+        uint_element(&mut ebml, EBML_MAX_SIZE_LENGTH, 8);
+        element(&mut ebml, DOC_TYPE, b"webm");
+        uint_element(&mut ebml, DOC_TYPE_VERSION, 4);
+        uint_element(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+        element(&mut out, EBML, &ebml);
+
+        write_id(&mut out, SEGMENT);
+        out.extend_from_slice(&UNKNOWN_SIZE);
+
+        let app = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+        let mut info = Vec::new();
+        uint_element(&mut info, TIMECODE_SCALE, 1_000_000);
+        element(&mut info, MUXING_APP, app.as_bytes());
+        element(&mut info, WRITING_APP, app.as_bytes());
+        element(&mut out, INFO, &info);
+
+        let mut codec_private = self.av1_config.clone();
+        codec_private.extend_from_slice(sequence_header);
+        let mut video = Vec::new();
+        uint_element(&mut video, PIXEL_WIDTH, self.width as u64);
+        uint_element(&mut video, PIXEL_HEIGHT, self.height as u64);
+        let mut track = Vec::new();
+        uint_element(&mut track, TRACK_NUMBER, 1);
+        uint_element(&mut track, TRACK_UID, 1);
+        uint_element(&mut track, TRACK_TYPE, 1);
+        uint_element(&mut track, FLAG_LACING, 0);
+        element(&mut track, CODEC_ID, b"V_AV1");
+        element(&mut track, CODEC_PRIVATE, &codec_private);
+        element(&mut track, VIDEO, &video);
+        let mut tracks = Vec::new();
+        element(&mut tracks, TRACK_ENTRY, &track);
+        element(&mut out, TRACKS, &tracks);
+        out
+    }
+
+    /// Returns the bytes to append for one encoded temporal unit shown at
+    /// `timestamp_ms`. The first call also emits the file header.
+    pub fn frame(&mut self, packet: &[u8], timestamp_ms: u64, keyframe: bool) -> Vec<u8> {
+        let obus = obus(packet);
+        let mut out = Vec::new();
+        if !self.header_written {
+            let sequence_header = obus
+                .iter()
+                .find(|(t, _)| *t == OBU_SEQUENCE_HEADER)
+                .map_or(&[][..], |(_, data)| *data);
+            out = self.header(sequence_header);
+            self.header_written = true;
+        }
+
+        let new_cluster = match self.cluster_start {
+            None => true,
+            Some(start) => {
+                keyframe || timestamp_ms < start || timestamp_ms - start > MAX_CLUSTER_SPAN_MS
+            }
+        };
+        if new_cluster {
+            write_id(&mut out, CLUSTER);
+            out.extend_from_slice(&UNKNOWN_SIZE);
+            uint_element(&mut out, TIMECODE, timestamp_ms);
+            self.cluster_start = Some(timestamp_ms);
+        }
+        let relative = (timestamp_ms - self.cluster_start.unwrap_or(timestamp_ms)) as i16;
+
+        // Matroska stores temporal units without temporal delimiters
+        let mut block = vec![0x81];
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if keyframe { 0x80 } else { 0x00 });
+        for (obu_type, data) in obus {
+            if obu_type != OBU_TEMPORAL_DELIMITER {
+                block.extend_from_slice(data);
+            }
+        }
+        element(&mut out, SIMPLE_BLOCK, &block);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(size: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_size(&mut out, size);
+        out
+    }
+
+    #[test]
+    fn sizes_use_the_shortest_encoding() {
+        assert_eq!(size(0), [0x80]);
+        assert_eq!(size(126), [0xFE]);
+        assert_eq!(size(300), [0x41, 0x2C]);
+        assert_eq!(size(16382), [0x7F, 0xFE]);
+        assert_eq!(size(1 << 40), [0x05, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn sizes_skip_the_unknown_marker() {
+        assert_eq!(size(127), [0x40, 0x7F]);
+        assert_eq!(size(16383), [0x20, 0x3F, 0xFF]);
+    }
+
+    #[test]
+    fn ids_and_uints_drop_leading_zeros() {
+        let mut out = Vec::new();
+        write_id(&mut out, SIMPLE_BLOCK);
+        write_id(&mut out, CLUSTER);
+        assert_eq!(out, [0xA3, 0x1F, 0x43, 0xB6, 0x75]);
+
+        let mut out = Vec::new();
+        uint_element(&mut out, TRACK_NUMBER, 0);
+        uint_element(&mut out, TIMECODE, 0x1234);
+        assert_eq!(out, [0xD7, 0x81, 0x00, 0xE7, 0x82, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn splits_obus() {
+        // A temporal delimiter, then a sized sequence header, then an
+        // unsized frame OBU running to the end
+        let packet = [0x12, 0x00, 0x0A, 0x02, 0xAA, 0xBB, 0x30, 0xCC];
+        let obus = obus(&packet);
+        let types: Vec<u8> = obus.iter().map(|(t, _)| *t).collect();
+        assert_eq!(types, [OBU_TEMPORAL_DELIMITER, OBU_SEQUENCE_HEADER, 6]);
+        assert_eq!(obus[1].1, [0x0A, 0x02, 0xAA, 0xBB]);
+        assert_eq!(obus[2].1, [0x30, 0xCC]);
+    }
+
+    #[test]
+    fn starts_clusters_on_keyframes() {
+        let mut writer = WebmWriter::new(64, 48, vec![0x81, 0, 0, 0]);
+        let first = writer.frame(&[0x30, 0xCC], 0, true);
+        assert!(first.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]));
+        let cluster = [0x1F, 0x43, 0xB6, 0x75];
+        let has_cluster = |data: &[u8]| data.windows(4).any(|w| w == cluster);
+        assert!(!has_cluster(&writer.frame(&[0x30, 0xCC], 33, false)));
+        let keyframe = writer.frame(&[0x30, 0xCC], 66, true);
+        assert!(keyframe.starts_with(&cluster));
+        assert!(has_cluster(&writer.frame(&[0x30, 0xCC], 40_000, false)));
+    }
+}
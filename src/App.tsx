@@ -1,10 +1,7 @@
 
 import { useState, useEffect, useRef } from "react";
-import { invoke } from "@tauri-apps/api/core";
-import { save, open } from "@tauri-apps/plugin-dialog";
+import { invoke, Channel, convertFileSrc } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
-import { writeImage } from "@tauri-apps/plugin-clipboard-manager";
-import { Image as TauriImage } from "@tauri-apps/api/image";
 import ReactCrop, { Crop, PixelCrop } from "react-image-crop";
 import "react-image-crop/dist/ReactCrop.css";
 import "./App.css";
@@ -12,28 +9,185 @@ import ImageEditor, { ImageEditorRef } from "./ImageEditor";
 
 interface WindowInfo {
   id: number;
+  pid: number;
   title: string;
   app_name: string;
+  x: number;
+  y: number;
+  z: number;
   width: number;
   height: number;
+  monitor_id: number | null;
+  is_focused: boolean;
+  is_minimized: boolean;
+  is_maximized: boolean;
 }
 
 interface MonitorInfo {
   id: number;
+  stable_id: string;
   name: string;
+  x: number;
+  y: number;
   width: number;
   height: number;
+  scale_factor: number;
+  rotation: number;
+  frequency: number;
   is_primary: boolean;
+  is_builtin: boolean;
+}
+
+// Commands reject with { code, message, ... }; see CaptureError in src-tauri/src/error.rs
+interface CommandError {
+  code: string;
+  message: string;
+}
+
+function errorMessage(error: unknown): string {
+  if (typeof error === "object" && error !== null && "message" in error) {
+    return (error as CommandError).message;
+  }
+  return String(error);
+}
+
+// A capture held in the Rust-side store; pixels are fetched by ID
+interface CaptureMeta {
+  id: number;
+  width: number;
+  height: number;
+  parent: number | null;
+  created_at: number;
+}
+
+// Monitor selector value for the stitched virtual-desktop capture
+const ALL_MONITORS = -1;
+
+interface WindowThumbnail {
+  width: number;
+  height: number;
+  version: number; // Changes when the image is re-taken
+}
+
+interface WindowThumbnails {
+  thumbnails: Record<number, WindowThumbnail>;
+  failures: { windowId: number; code: string; message: string }[];
 }
 
 type Mode = "fullscreen" | "window" | "area" | "record";
 
+// Persisted preferences; see src-tauri/src/settings.rs
+interface Settings {
+  version: number;
+  defaultPath: string | null;
+  autoSave: boolean;
+  imageTemplate: string;
+  videoTemplate: string;
+  onCollision: "suffix" | "overwrite" | "ask";
+}
+
+// Builds a file name from the configured template. When the collision policy
+// is "ask" and the name is taken, the existing path comes back with `taken` set
+async function templatedPath(args: Record<string, unknown>): Promise<{ path: string; taken: boolean }> {
+  try {
+    return { path: await invoke<string>("next_save_path", args), taken: false };
+  } catch (error) {
+    const e = error as CommandError & { path?: string };
+    if (e.code === "file_exists" && e.path) {
+      return { path: e.path, taken: true };
+    }
+    throw error;
+  }
+}
+
+// Global shortcut bindings; see src-tauri/src/shortcuts.rs
+type ShortcutAction =
+  | "areaCapture"
+  | "fullscreen"
+  | "activeWindow"
+  | "windowAtCursor"
+  | "toggleRecording"
+  | "repeatLastRegion"
+  | "saveReplay";
+
+const SHORTCUT_ACTIONS: Record<ShortcutAction, string> = {
+  areaCapture: "Area capture",
+  fullscreen: "Full screen",
+  activeWindow: "Active window",
+  windowAtCursor: "Window under cursor",
+  toggleRecording: "Start/stop recording",
+  repeatLastRegion: "Repeat last region",
+  saveReplay: "Save replay",
+};
+
+interface Binding {
+  action: ShortcutAction;
+  accelerator: string;
+}
+
+interface RegistrationFailure extends Binding {
+  reason: string;
+}
+
+interface KeymapStatus {
+  keymap: { bindings: Binding[] };
+  failures: RegistrationFailure[];
+  errors: string[];
+}
+
+// Failures in work started by a shortcut or the tray rather than the UI
+interface BackgroundError {
+  context: string;
+  code: string;
+  message: string;
+}
+
+interface StreamError {
+  streamId: number;
+  code: string;
+  message: string;
+}
+
+type RecordingFormat = "webm" | "gif";
+type RecordingState = "idle" | "recording" | "paused" | "finalizing";
+type StopReason = "requested" | "maxDuration" | "maxSize";
+type GifPalette = "global" | "perFrame";
+type GifDither = "none" | "ordered" | "errorDiffusion";
+// "followWindow" records the screen under the window at a fixed size
+type RecordSource = "screen" | "window" | "followWindow";
+
+interface RecordingResult {
+  session: number;
+  extension: string;
+  frames: number;
+  dropped: number;
+  bytes: number;
+  durationMs: number;
+}
+
+interface RecordingStateEvent {
+  state: RecordingState;
+  reason: StopReason | null;
+}
+
+interface RecordingStopped extends RecordingResult {
+  reason: StopReason;
+}
+
+// Stored captures are loaded by the webview straight from the capture protocol
+function captureUrl(capture: CaptureMeta): string {
+  return convertFileSrc(String(capture.id), "capture");
+}
+
 function App() {
-  const [image, setImage] = useState<string | null>(null);
+  // The capture being shown and annotated; it stays in the backend store
+  const [image, setImage] = useState<CaptureMeta | null>(null);
+  const imageRef = useRef<CaptureMeta | null>(null);
   const [loading, setLoading] = useState(false);
   const [status, setStatus] = useState("");
   const [mode, setMode] = useState<Mode>("fullscreen");
   const [windows, setWindows] = useState<WindowInfo[]>([]);
+  const [thumbnails, setThumbnails] = useState<Record<number, WindowThumbnail>>({});
   const [monitors, setMonitors] = useState<MonitorInfo[]>([]);
   const [selectedWindowId, setSelectedWindowId] = useState<number | null>(null);
   const [selectedMonitorId, setSelectedMonitorId] = useState<number | null>(null);
@@ -42,19 +196,35 @@ function App() {
   const [crop, setCrop] = useState<Crop>();
   const [completedCrop, setCompletedCrop] = useState<PixelCrop>();
   const imgRef = useRef<HTMLImageElement>(null);
-  const [originalImage, setOriginalImage] = useState<string | null>(null); // Store full screenshot for cropping
+  const [areaCapture, setAreaCapture] = useState<CaptureMeta | null>(null); // Full screenshot kept in the backend for cropping
 
   // Settings state
   const [showSettings, setShowSettings] = useState(false);
   const [defaultPath, setDefaultPath] = useState<string | null>(null);
   const [autoSave, setAutoSave] = useState(false);
+  const [settings, setSettings] = useState<Settings | null>(null);
+  const [bindings, setBindings] = useState<Binding[]>([]);
+  const [shortcutFailures, setShortcutFailures] = useState<RegistrationFailure[]>([]);
+  const [keymapErrors, setKeymapErrors] = useState<string[]>([]);
 
   // Recording state
-  const [isRecording, setIsRecording] = useState(false);
-  const mediaRecorderRef = useRef<MediaRecorder | null>(null);
-  const recordedChunksRef = useRef<Blob[]>([]);
+  const [recordingState, setRecordingState] = useState<RecordingState>("idle");
+  const isRecording = recordingState === "recording" || recordingState === "paused";
+  const [maxMinutes, setMaxMinutes] = useState("");
+  const [maxMegabytes, setMaxMegabytes] = useState("");
+  const [recordFormat, setRecordFormat] = useState<RecordingFormat>("webm");
+  const [recordSource, setRecordSource] = useState<RecordSource>("screen");
+  const [gifPalette, setGifPalette] = useState<GifPalette>("global");
+  const [gifDither, setGifDither] = useState<GifDither>("ordered");
+  const [replayActive, setReplayActive] = useState(false);
   const canvasRef = useRef<HTMLCanvasElement>(null);
   const unlistenRef = useRef<(() => void) | null>(null);
+  // Latest start/stop for the toggle-recording shortcut listener
+  const toggleRecordingRef = useRef<() => void>(() => {});
+  // Latest save flow for replays encoded by the save-replay shortcut
+  const replayReadyRef = useRef<(result: RecordingResult) => void>(() => {});
+  // Latest save flow for recordings stopped by a limit or from the tray
+  const recordingStoppedRef = useRef<(stopped: RecordingStopped) => void>(() => {});
 
   // Image Editor Ref
   const editorRef = useRef<ImageEditorRef>(null);
@@ -65,40 +235,162 @@ function App() {
     } else {
       fetchMonitors();
     }
+    if (mode === "record" && recordSource !== "screen") {
+      fetchWindows();
+    }
+  }, [mode, recordSource]);
+
+  useEffect(() => {
+    // Only full screen can capture all monitors at once
+    if (mode !== "fullscreen" && selectedMonitorId === ALL_MONITORS) {
+      const primary = monitors.find(m => m.is_primary) ?? monitors[0];
+      setSelectedMonitorId(primary ? primary.id : null);
+    }
   }, [mode]);
 
   useEffect(() => {
-    const savedPath = localStorage.getItem("defaultPath");
-    if (savedPath) {
-      setDefaultPath(savedPath);
+    function applySettings(settings: Settings) {
+      setSettings(settings);
+      setDefaultPath(settings.defaultPath);
+      setAutoSave(settings.autoSave);
     }
-    const savedAutoSave = localStorage.getItem("autoSave");
-    if (savedAutoSave) {
-      setAutoSave(savedAutoSave === "true");
+
+    // Settings used to live in localStorage; hand the raw values to Rust once,
+    // which migrates them. The default folder is not carried over because
+    // only a folder dialog may set it
+    async function loadSettings() {
+      const savedPath = localStorage.getItem("defaultPath");
+      const savedAutoSave = localStorage.getItem("autoSave");
+      if (savedPath !== null || savedAutoSave !== null) {
+        const values: Record<string, string> = {};
+        if (savedPath !== null) values.defaultPath = savedPath;
+        if (savedAutoSave !== null) values.autoSave = savedAutoSave;
+        await invoke("import_legacy_settings", { values });
+        localStorage.removeItem("defaultPath");
+        localStorage.removeItem("autoSave");
+        if (savedPath) {
+          applySettings(await invoke<Settings>("get_settings"));
+          setStatus(`Your default folder (${savedPath}) needs to be chosen again.`);
+          if (window.confirm(`Saving now needs the default folder to be picked in a folder dialog.\n\nChoose it again? It was:\n${savedPath}`)) {
+            await selectDefaultFolder();
+          }
+          return;
+        }
+      }
+      applySettings(await invoke<Settings>("get_settings"));
     }
+    loadSettings().catch((error) => console.error(error));
+    const unlistenSettings = listen<Settings>("settings-changed", (event) => applySettings(event.payload));
+    const unlistenSaved = listen<string>("capture-saved", (event) =>
+        setStatus(`Saved to ${event.payload}`));
+    const unlistenBackgroundError = listen<BackgroundError>("background-error", (event) =>
+        setStatus(`${event.payload.context}: ${event.payload.message}`));
+    const unlistenStreamError = listen<StreamError>("stream-error", (event) =>
+        setStatus(`Stream ${event.payload.streamId}: ${event.payload.message}`));
+
+    invoke<KeymapStatus>("get_keymap")
+      .then((status) => {
+        setBindings(status.keymap.bindings);
+        setShortcutFailures(status.failures);
+        setKeymapErrors(status.errors);
+      })
+      .catch((error) => console.error(error));
 
-    const unlistenCapture = listen<string>("start-area-capture", (event) => {
-        setOriginalImage(event.payload);
-        setImage(null);
+    const unlistenCapture = listen<CaptureMeta>("start-area-capture", (event) => {
+        setAreaCapture(event.payload);
+        showCapture(null);
         setMode("area");
         setStatus("Select area to crop (triggered via shortcut)");
     });
 
+    // Shortcut captures arrive as stored captures; show them like a normal capture
+    function showShortcutCapture(capture: CaptureMeta, captureMode: Mode, label: string) {
+        setAreaCapture(null);
+        setMode(captureMode);
+        showCapture(capture);
+        setStatus(`${label} captured (triggered via shortcut)`);
+    }
+
+    const unlistenWindow = listen<CaptureMeta>("window-captured", (event) =>
+        showShortcutCapture(event.payload, "window", "Window"));
+    const unlistenScreen = listen<CaptureMeta>("screen-captured", (event) =>
+        showShortcutCapture(event.payload, "fullscreen", "Screen"));
+    const unlistenToggle = listen("toggle-recording", () => toggleRecordingRef.current());
+    const unlistenReplay = listen<RecordingResult>("replay-ready", (event) =>
+        replayReadyRef.current(event.payload));
+
+    // Recording state lives in Rust; the tray and limits can change it too
+    invoke<RecordingState>("get_recording_state").then(setRecordingState).catch(console.error);
+    const unlistenRecordingState = listen<RecordingStateEvent>("recording-state", (event) => {
+        setRecordingState(event.payload.state);
+        if (event.payload.state === "finalizing" && unlistenRef.current) {
+            unlistenRef.current();
+            unlistenRef.current = null;
+        }
+    });
+    const unlistenStopped = listen<RecordingStopped>("recording-stopped", (event) =>
+        recordingStoppedRef.current(event.payload));
+    // The backend only keeps the most recent captures
+    const unlistenEvicted = listen<number[]>("captures-evicted", (event) => {
+        setAreaCapture((current) => {
+            if (current && event.payload.includes(current.id)) {
+                setStatus("The area capture expired; take a new one.");
+                return null;
+            }
+            return current;
+        });
+        if (imageRef.current && event.payload.includes(imageRef.current.id)) {
+            imageRef.current = null;
+            setImage(null);
+            setStatus("The capture expired; take a new one.");
+        }
+    });
+
     return () => {
         unlistenCapture.then(f => f());
+        unlistenWindow.then(f => f());
+        unlistenScreen.then(f => f());
+        unlistenToggle.then(f => f());
+        unlistenReplay.then(f => f());
+        unlistenRecordingState.then(f => f());
+        unlistenStopped.then(f => f());
+        unlistenEvicted.then(f => f());
+        unlistenSettings.then(f => f());
+        unlistenSaved.then(f => f());
+        unlistenBackgroundError.then(f => f());
+        unlistenStreamError.then(f => f());
     };
   }, []);
 
   async function fetchWindows() {
     try {
-      const wins = await invoke<WindowInfo[]>("get_windows");
+      // Minimized windows capture black, so leave them out of the picker
+      const wins = await invoke<WindowInfo[]>("get_windows", { includeMinimized: false });
       setWindows(wins);
       if (wins.length > 0 && !selectedWindowId) {
         setSelectedWindowId(wins[0].id);
       }
+      const previews = await invoke<WindowThumbnails>("get_window_thumbnails", {
+        maxSize: 160,
+        includeMinimized: false,
+      });
+      setThumbnails(previews.thumbnails);
+      for (const failure of previews.failures) {
+        console.warn(`No thumbnail for window ${failure.windowId}: ${failure.message}`);
+      }
     } catch (error) {
       console.error(error);
-      setStatus(`Error fetching windows: ${error}`);
+      setStatus(`Error fetching windows: ${errorMessage(error)}`);
+    }
+  }
+
+  // Shows a stored capture, dropping the one shown before from the backend
+  function showCapture(capture: CaptureMeta | null) {
+    const previous = imageRef.current;
+    imageRef.current = capture;
+    setImage(capture);
+    if (previous && previous.id !== capture?.id) {
+      invoke("discard_capture", { id: previous.id }).catch(console.error);
     }
   }
 
@@ -113,7 +405,7 @@ function App() {
       }
     } catch (error) {
       console.error(error);
-      setStatus(`Error fetching monitors: ${error}`);
+      setStatus(`Error fetching monitors: ${errorMessage(error)}`);
     }
   }
 
@@ -121,25 +413,30 @@ function App() {
     setLoading(true);
     setStatus("Capturing...");
     try {
-      let result: string;
+      // Captures stay in the backend; only the preview is loaded
+      let result: CaptureMeta;
       if (mode === "window" && selectedWindowId) {
-        result = await invoke<string>("capture_window", { id: selectedWindowId });
-        setImage(result);
-        setOriginalImage(null);
+        result = await invoke<CaptureMeta>("take_capture", {
+          source: { kind: "window", id: selectedWindowId },
+        });
+      } else if (mode === "area") {
+        const meta = await invoke<CaptureMeta>("take_capture", {
+          source: { kind: "monitor", monitorId: selectedMonitorId },
+        });
+        setAreaCapture(meta);
+        showCapture(null);
+        setStatus("Select area to crop");
+        return;
+      } else if (selectedMonitorId === ALL_MONITORS) {
+        result = (await invoke<{ capture: CaptureMeta }>("capture_all_monitors")).capture;
       } else {
-        // Fullscreen or Area
-        // Pass selectedMonitorId if available
-        result = await invoke<string>("capture_screen", { monitorId: selectedMonitorId });
-        if (mode === "area") {
-          setOriginalImage(result);
-          setImage(null);
-          setStatus("Select area to crop");
-        } else {
-          setImage(result);
-          setOriginalImage(null);
-        }
+        result = await invoke<CaptureMeta>("take_capture", {
+          source: { kind: "monitor", monitorId: selectedMonitorId },
+        });
       }
-      
+      showCapture(result);
+      setAreaCapture(null);
+
       if (mode !== "area") {
         setStatus("Captured!");
         if (autoSave) {
@@ -149,261 +446,342 @@ function App() {
       }
     } catch (error) {
       console.error(error);
-      setStatus(`Error: ${error}`);
+      setStatus(`Error: ${errorMessage(error)}`);
     } finally {
       setLoading(false);
     }
   }
 
   async function confirmCrop() {
-    if (completedCrop && imgRef.current && originalImage) {
-      const canvas = document.createElement("canvas");
-      // Use natural dimensions for high DPI correctness
-      const scaleX = imgRef.current.naturalWidth / imgRef.current.width;
-      const scaleY = imgRef.current.naturalHeight / imgRef.current.height;
-      
-      canvas.width = completedCrop.width * scaleX;
-      canvas.height = completedCrop.height * scaleY;
-      
-      const ctx = canvas.getContext("2d");
-
-      if (ctx) {
-        // Improve scaling quality
-        ctx.imageSmoothingEnabled = true;
-        ctx.imageSmoothingQuality = "high";
-
-        ctx.drawImage(
-          imgRef.current,
-          completedCrop.x * scaleX,
-          completedCrop.y * scaleY,
-          completedCrop.width * scaleX,
-          completedCrop.height * scaleY,
-          0,
-          0,
-          canvas.width,
-          canvas.height
-        );
-        const base64 = canvas.toDataURL("image/png").split(",")[1];
-        setImage(base64);
-        setOriginalImage(null);
-        
-        await copyToClipboard(base64);
+    if (completedCrop && imgRef.current && areaCapture) {
+      try {
+        // Convert the on-screen selection to capture pixels for a pixel-exact crop in Rust
+        const scaleX = imgRef.current.naturalWidth / imgRef.current.width;
+        const scaleY = imgRef.current.naturalHeight / imgRef.current.height;
+
+        const cropped = await invoke<CaptureMeta>("crop_capture", {
+          id: areaCapture.id,
+          x: Math.round(completedCrop.x * scaleX),
+          y: Math.round(completedCrop.y * scaleY),
+          width: Math.max(1, Math.round(completedCrop.width * scaleX)),
+          height: Math.max(1, Math.round(completedCrop.height * scaleY)),
+        });
+        await invoke("copy_capture", { id: cropped.id });
+        await invoke("discard_capture", { id: areaCapture.id });
+
+        showCapture(cropped);
+        setAreaCapture(null);
         setStatus("Cropped & Copied to Clipboard!");
-        
+
         if (autoSave) {
-            saveImage(base64);
+            saveImage(cropped);
         }
+      } catch (error) {
+        console.error(error);
+        setStatus(`Crop Error: ${errorMessage(error)}`);
       }
     }
   }
 
-  async function copyToClipboard(dataBase64: string) {
+  async function copyToClipboard(capture: CaptureMeta) {
       try {
-          const binaryString = atob(dataBase64);
-          const len = binaryString.length;
-          const bytes = new Uint8Array(len);
-          for (let i = 0; i < len; i++) {
-              bytes[i] = binaryString.charCodeAt(i);
-          }
-          
-          const img = await TauriImage.fromBytes(bytes);
-          await writeImage(img);
-          console.log("Image copied to clipboard");
+          await invoke("copy_capture", { id: capture.id });
       } catch (err) {
           console.error("Clipboard Error:", err);
-          setStatus(`Clipboard Error: ${err}`);
+          setStatus(`Clipboard Error: ${errorMessage(err)}`);
       }
   }
 
 
+  function recordingOptions() {
+    const limits = {
+      maxDurationSecs: Number(maxMinutes) > 0 ? Math.ceil(Number(maxMinutes) * 60) : null,
+      maxBytes: Number(maxMegabytes) > 0 ? Math.ceil(Number(maxMegabytes) * 1024 * 1024) : null,
+    };
+    // GIFs get big fast, so they are smaller and choppier than video
+    return recordFormat === "gif"
+      ? { format: "gif", fps: 15, maxWidth: 960, maxHeight: 540, gif: { palette: gifPalette, dither: gifDither }, ...limits }
+      : { format: "webm", ...limits };
+  }
+
+  // What recordings and the replay buffer capture from
+  function recordingSource() {
+    if (recordSource === "screen" || !selectedWindowId) {
+      return { kind: "monitor", monitorId: selectedMonitorId };
+    }
+    if (recordSource === "window") {
+      return { kind: "window", id: selectedWindowId };
+    }
+    const [width, height] = recordFormat === "gif" ? [960, 540] : [1920, 1080];
+    return { kind: "followWindow", id: selectedWindowId, width, height };
+  }
+
   async function startRecording() {
     try {
         if (!canvasRef.current) {
             setStatus("Error: Canvas not initialized");
             return;
         }
-
-        // 0. Initialize Canvas Size to Monitor Size (Crucial for Stream Resolution)
-        const currentMonitor = monitors.find(m => m.id === selectedMonitorId);
-        if (currentMonitor) {
-            canvasRef.current.width = currentMonitor.width;
-            canvasRef.current.height = currentMonitor.height;
-            console.log(`Initialized canvas to ${currentMonitor.width}x${currentMonitor.height}`);
-        } else {
-             // Fallback
-             canvasRef.current.width = 1920;
-             canvasRef.current.height = 1080;
-        }
-
-        // 1. Setup Canvas Context
         const ctx = canvasRef.current.getContext("2d");
         if (!ctx) return;
 
-        // 2. Start Listener
-        const unlisten = await listen<string>("screen-frame", (event) => {
-            const img = new Image();
-            img.onload = () => {
-                if (canvasRef.current && ctx) {
-                    // Resize if strictly needed (e.g. monitor resolution changed or was wrong)
-                    if (canvasRef.current.width !== img.width || canvasRef.current.height !== img.height) {
-                        canvasRef.current.width = img.width;
-                        canvasRef.current.height = img.height;
-                    }
-                    ctx.drawImage(img, 0, 0);
+        // Frames are encoded to a file in Rust; only low-rate previews come back
+        const onPreview = new Channel<ArrayBuffer>();
+        let active = true;
+        onPreview.onmessage = async (frame) => {
+            if (!active) return;
+            const bitmap = await createImageBitmap(new Blob([frame], { type: "image/jpeg" }));
+            if (canvasRef.current && ctx) {
+                if (canvasRef.current.width !== bitmap.width || canvasRef.current.height !== bitmap.height) {
+                    canvasRef.current.width = bitmap.width;
+                    canvasRef.current.height = bitmap.height;
                 }
-            };
-            img.src = "data:image/jpeg;base64," + event.payload;
-        });
-        unlistenRef.current = unlisten;
-
-        // 3. Start Backend Streaming
-        await invoke("start_streaming", { monitorId: selectedMonitorId });
-
-        // 4. Start MediaRecorder
-        const stream = canvasRef.current.captureStream(30); // 30 FPS
-        const mediaRecorder = new MediaRecorder(stream, { 
-            mimeType: "video/webm;codecs=vp9", 
-            videoBitsPerSecond: 12000000 // 12 Mbps for high quality
-        });
-        mediaRecorderRef.current = mediaRecorder;
-        recordedChunksRef.current = [];
-
-        mediaRecorder.ondataavailable = (event) => {
-            if (event.data.size > 0) {
-            recordedChunksRef.current.push(event.data);
+                ctx.drawImage(bitmap, 0, 0);
             }
+            bitmap.close();
         };
+        unlistenRef.current = () => { active = false; };
 
-        mediaRecorder.onstop = async () => {
-            const blob = new Blob(recordedChunksRef.current, { type: "video/webm" });
-            const buffer = await blob.arrayBuffer();
-            const bytes = new Uint8Array(buffer);
-            
-            // Save video
-            saveVideo(Array.from(bytes));
-            
-            // Clean up
-            if (unlistenRef.current) {
-                unlistenRef.current();
-                unlistenRef.current = null;
-            }
-            setIsRecording(false);
-            setStatus("Recording finished and saved.");
-        };
-
-        mediaRecorder.start();
-        setIsRecording(true);
+        await invoke<number>("start_recording", {
+            source: recordingSource(),
+            options: recordingOptions(),
+            onPreview,
+        });
         setStatus("Recording...");
 
     } catch (err) {
       console.error("Error starting recording:", err);
-      setStatus(`Recording Error: ${err}`);
-      // Cleanup if failed
-      await invoke("stop_streaming");
+      setStatus(`Recording Error: ${errorMessage(err)}`);
       if (unlistenRef.current) unlistenRef.current();
     }
   }
 
   async function stopRecording() {
-    if (mediaRecorderRef.current && isRecording) {
-      mediaRecorderRef.current.stop();
+    if (!isRecording) return;
+    setStatus("Finishing recording...");
+    try {
+      const result = await invoke<RecordingResult>("stop_recording");
+      setStatus(`Recording finished: ${result.frames} frames, ${result.dropped} dropped.`);
+      await saveVideo(result.session, result.extension);
+    } catch (err) {
+      console.error("Error stopping recording:", err);
+      setStatus(`Recording Error: ${errorMessage(err)}`);
     }
-    await invoke("stop_streaming");
   }
 
-  async function saveVideo(bytes: number[]) {
+  async function togglePause() {
     try {
-      const now = new Date();
-      const timestamp = now.toISOString().replace(/[:.]/g, "-").slice(0, 19);
-      const filename = `recording_${timestamp}.webm`;
-      
-      let path: string | null = null;
+      await invoke(recordingState === "paused" ? "resume_recording" : "pause_recording");
+      setStatus(recordingState === "paused" ? "Recording..." : "Recording paused.");
+    } catch (err) {
+      console.error(err);
+      setStatus(`Recording Error: ${errorMessage(err)}`);
+    }
+  }
 
-      if (autoSave && defaultPath) {
-        path = `${defaultPath}/${filename}`;
-      } else {
-        path = await save({
-            defaultPath: defaultPath ? `${defaultPath}/${filename}` : filename,
-            filters: [{ name: "Video", extensions: ["webm"] }],
-        });
-      }
+  recordingStoppedRef.current = (stopped) => {
+    const why = stopped.reason === "maxDuration" ? "time limit reached"
+      : stopped.reason === "maxSize" ? "size limit reached"
+      : "stopped from the tray";
+    setStatus(`Recording finished (${why}): ${stopped.frames} frames, ${stopped.dropped} dropped.`);
+    saveVideo(stopped.session, stopped.extension);
+  };
 
-      if (path) {
-        await invoke("save_video", { path, data: bytes });
-        setStatus(`Video saved to ${path}`);
+  toggleRecordingRef.current = () => {
+    if (isRecording) {
+      stopRecording();
+    } else {
+      setMode("record");
+      startRecording();
+    }
+  };
+
+  async function saveVideo(session: number, extension: string) {
+    try {
+      const { path: suggested, taken } = await templatedPath({
+        kind: "video",
+        mode: "record",
+        extension,
+        source: currentSource(),
+      });
+
+      const choosePath = () => invoke<string | null>("choose_save_path", {
+          defaultPath: suggested,
+          filters: [{ name: extension === "gif" ? "GIF" : "Video", extensions: [extension] }],
+      });
+      let path = autoSave && defaultPath && !taken ? suggested : await choosePath();
+
+      // A failed move keeps the recording, so let the user pick another place
+      while (path) {
+        try {
+          await invoke("finalize_video_file", { session, path });
+          setStatus(`Video saved to ${path}`);
+          return;
+        } catch (error) {
+          const retry = window.confirm(
+            `Could not save to ${path}: ${errorMessage(error)}\n\nChoose another location? Cancel discards the recording.`
+          );
+          path = retry ? await choosePath() : null;
+        }
       }
+      await invoke("abort_video_file", { session });
+      setStatus("Recording discarded.");
     } catch (error) {
       console.error(error);
-      setStatus(`Save Video Error: ${error}`);
+      setStatus(`Save Video Error: ${errorMessage(error)}`);
+    }
+  }
+
+  replayReadyRef.current = (result) => {
+    saveVideo(result.session, result.extension);
+  };
+
+  async function toggleReplay() {
+    try {
+      if (replayActive) {
+        await invoke("stop_replay");
+        setReplayActive(false);
+        setStatus("Replay buffer stopped.");
+      } else {
+        await invoke("start_replay", { source: recordingSource(), options: { seconds: 30 } });
+        setReplayActive(true);
+        setStatus("Replay buffer running: keeping the last 30 seconds.");
+      }
+    } catch (err) {
+      console.error(err);
+      setStatus(`Replay Error: ${errorMessage(err)}`);
+    }
+  }
+
+  async function saveReplay() {
+    try {
+      setStatus("Saving replay...");
+      const result = await invoke<RecordingResult>("save_replay", { options: recordingOptions() });
+      await saveVideo(result.session, result.extension);
+    } catch (err) {
+      console.error(err);
+      setStatus(`Replay Error: ${errorMessage(err)}`);
     }
   }
 
   async function selectDefaultFolder() {
     try {
-      const selected = await open({
-        directory: true,
-        multiple: false,
-      });
-      if (selected && typeof selected === "string") {
-        setDefaultPath(selected);
-        localStorage.setItem("defaultPath", selected);
+      // The Rust side opens the dialog so it knows the folder was picked by the user
+      await invoke("choose_default_folder");
+    } catch (error) {
+      console.error(error);
+      setStatus(`Error selecting folder: ${errorMessage(error)}`);
+    }
+  }
+
+  async function toggleAutoSave() {
+      try {
+          await invoke("update_settings", { patch: { autoSave: !autoSave } });
+      } catch (error) {
+          console.error(error);
+          setStatus(`Settings Error: ${errorMessage(error)}`);
       }
+  }
+
+  function updateBinding(index: number, change: Partial<Binding>) {
+    setBindings(bindings.map((b, i) => (i === index ? { ...b, ...change } : b)));
+  }
+
+  async function saveKeymap() {
+    try {
+      const status = await invoke<KeymapStatus>("set_keymap", { keymap: { bindings } });
+      setBindings(status.keymap.bindings);
+      setShortcutFailures(status.failures);
+      setKeymapErrors(status.errors);
+      setStatus(status.failures.length ? "Some shortcuts could not be registered" : "Shortcuts saved");
     } catch (error) {
       console.error(error);
-      setStatus(`Error selecting folder: ${error}`);
+      setStatus(`Shortcut Error: ${errorMessage(error)}`);
     }
   }
 
-  function toggleAutoSave() {
-      const newValue = !autoSave;
-      setAutoSave(newValue);
-      localStorage.setItem("autoSave", String(newValue));
+  // What the current mode captures from, for filename templates
+  function currentSource() {
+    if (mode === "window" && selectedWindowId) {
+      return { kind: "window", id: selectedWindowId };
+    }
+    if (selectedMonitorId !== null && selectedMonitorId !== ALL_MONITORS) {
+      return { kind: "monitor", monitorId: selectedMonitorId };
+    }
+    return null;
   }
 
-  async function saveImage(imgData?: string) {
-    let dataToSave = imgData || image;
-    
-    // If saving the current view (not a direct capture result) and editor is available, merge annotations
-    if (!imgData && image && editorRef.current) {
-        dataToSave = await editorRef.current.getMergedImage();
+  async function updateSettings(patch: Partial<Settings>) {
+    try {
+      await invoke("update_settings", { patch });
+    } catch (error) {
+      console.error(error);
+      setStatus(`Settings Error: ${errorMessage(error)}`);
     }
+  }
+
+  async function saveImage(direct?: CaptureMeta) {
+    const shown = direct ?? image;
+    if (!shown) return;
 
-    if (!dataToSave) return;
-    
     try {
-      // 1. Copy to Clipboard (already done in confirmCrop if coming from there, but good to ensure if saving manually)
-      await copyToClipboard(dataToSave);
+      // Annotations only exist in the webview, so only an annotated image is
+      // sent back; otherwise the stored capture is saved as it is
+      let capture = shown;
+      let imported = false;
+      if (!direct && editorRef.current?.hasAnnotations()) {
+        const merged = await editorRef.current.getMergedImage();
+        capture = await invoke<CaptureMeta>("import_capture", merged);
+        imported = true;
+      }
 
-      // 2. Save to File
-      const now = new Date();
-      const timestamp = now.toISOString().replace(/[:.]/g, "-").slice(0, 19);
-      const filename = `screenshot_${mode}_${timestamp}.png`;
+      // 1. Copy to Clipboard (already done in confirmCrop if coming from there, but good to ensure if saving manually)
+      await copyToClipboard(capture);
 
+      // 2. Save to File; encoding happens in Rust and the format follows the chosen extension
       let path: string | null = null;
+      try {
+        const { path: suggested, taken } = await templatedPath({
+          mode,
+          extension: "png",
+          captureId: capture.id,
+          source: currentSource(),
+        });
 
-      if (autoSave && defaultPath) {
-          path = `${defaultPath}/${filename}`;
-      } else {
-          path = await save({
-            defaultPath: defaultPath ? `${defaultPath}/${filename}` : filename,
+        if (autoSave && defaultPath && !taken) {
+          path = suggested;
+        } else {
+          path = await invoke<string | null>("choose_save_path", {
+            defaultPath: suggested,
             filters: [
-              {
-                name: "Image",
-                extensions: ["png"],
-              },
+              { name: "PNG", extensions: ["png"] },
+              { name: "JPEG", extensions: ["jpg", "jpeg"] },
+              { name: "WebP (lossless)", extensions: ["webp"] },
+              { name: "BMP", extensions: ["bmp"] },
+              { name: "TIFF", extensions: ["tif", "tiff"] },
+              { name: "QOI", extensions: ["qoi"] },
+              { name: "Icon", extensions: ["ico"] },
             ],
           });
+        }
+
+        if (path) {
+          await invoke("export_image", { id: capture.id, path });
+        }
+      } finally {
+        if (imported) {
+          await invoke("discard_capture", { id: capture.id });
+        }
       }
-      
+
       if (path) {
-        await invoke("save_image", { path, data: dataToSave });
         setStatus(`Saved to ${path} & Clipboard`);
       } else {
         setStatus("Copied to Clipboard (File save cancelled)");
       }
     } catch (error) {
       console.error(error);
-      setStatus(`Save Error: ${error}`);
+      setStatus(`Save Error: ${errorMessage(error)}`);
     }
   }
 
@@ -434,6 +812,73 @@ function App() {
                   Auto Save (Skip Dialog)
               </label>
           </div>
+          {settings && (
+            <>
+              <div className="setting-item">
+                <label>Screenshot Name:</label>
+                <input
+                  key={`image-${settings.imageTemplate}`}
+                  defaultValue={settings.imageTemplate}
+                  onBlur={(e) => updateSettings({ imageTemplate: e.target.value })}
+                />
+                <label>Recording Name:</label>
+                <input
+                  key={`video-${settings.videoTemplate}`}
+                  defaultValue={settings.videoTemplate}
+                  onBlur={(e) => updateSettings({ videoTemplate: e.target.value })}
+                />
+                <small>
+                  Tokens: {"{date} {date:%Y-%m} {time} {mode} {monitor} {app} {title} {width} {height} {counter:3}"}; use / for subfolders
+                </small>
+              </div>
+              <div className="setting-item">
+                <label>If File Exists:</label>
+                <select
+                  value={settings.onCollision}
+                  onChange={(e) => updateSettings({ onCollision: e.target.value as Settings["onCollision"] })}
+                >
+                  <option value="suffix">Add a number</option>
+                  <option value="overwrite">Overwrite</option>
+                  <option value="ask">Ask</option>
+                </select>
+              </div>
+            </>
+          )}
+          <div className="setting-item">
+            <label>Global Shortcuts:</label>
+            {bindings.map((binding, index) => (
+              <div key={index} className="shortcut-binding">
+                <select
+                  value={binding.action}
+                  onChange={(e) => updateBinding(index, { action: e.target.value as ShortcutAction })}
+                >
+                  {Object.entries(SHORTCUT_ACTIONS).map(([action, label]) => (
+                    <option key={action} value={action}>{label}</option>
+                  ))}
+                </select>
+                <input
+                  value={binding.accelerator}
+                  placeholder="e.g. CommandOrControl+Shift+F11"
+                  onChange={(e) => updateBinding(index, { accelerator: e.target.value })}
+                />
+                <button onClick={() => setBindings(bindings.filter((_, i) => i !== index))}>✕</button>
+              </div>
+            ))}
+            {keymapErrors.map((error, index) => (
+              <div key={`error-${index}`} className="shortcut-failure">{error}</div>
+            ))}
+            {shortcutFailures.map((f, index) => (
+              <div key={index} className="shortcut-failure">
+                {f.accelerator} ({SHORTCUT_ACTIONS[f.action]}): {f.reason}
+              </div>
+            ))}
+            <div className="row">
+              <button onClick={() => setBindings([...bindings, { action: "areaCapture", accelerator: "" }])}>
+                Add Shortcut
+              </button>
+              <button onClick={saveKeymap}>Save Shortcuts</button>
+            </div>
+          </div>
         </div>
       )}
 
@@ -477,7 +922,7 @@ function App() {
           </label>
         </div>
 
-        {mode === "window" && (
+        {(mode === "window" || (mode === "record" && recordSource !== "screen")) && (
           <div className="window-selector">
             <select
               value={selectedWindowId || ""}
@@ -485,7 +930,7 @@ function App() {
             >
               {windows.map((w) => (
                 <option key={w.id} value={w.id}>
-                  {w.app_name || "Unknown"} - {w.title.substring(0, 30)} ({w.width}x{w.height})
+                  {w.is_focused ? "● " : ""}{w.app_name || "Unknown"} - {w.title.substring(0, 30)} ({w.width}x{w.height} at {w.x},{w.y}, pid {w.pid})
                 </option>
               ))}
             </select>
@@ -493,8 +938,27 @@ function App() {
           </div>
         )}
 
+        {mode === "window" && (
+          <div className="window-thumbnails">
+            {windows.filter((w) => thumbnails[w.id]).map((w) => (
+              <div
+                key={w.id}
+                className={`window-thumbnail ${w.id === selectedWindowId ? "selected" : ""}`}
+                onClick={() => setSelectedWindowId(w.id)}
+                title={`${w.app_name} - ${w.title}`}
+              >
+                <img
+                  src={`${convertFileSrc(`thumbnail-${w.id}`, "capture")}?v=${thumbnails[w.id].version}`}
+                  alt={w.title}
+                />
+                <span>{(w.app_name || "Unknown").substring(0, 20)}</span>
+              </div>
+            ))}
+          </div>
+        )}
+
         {/* Show monitor selector for fullscreen, area AND record */}
-        {(mode === "fullscreen" || mode === "area" || mode === "record") && monitors.length > 1 && (
+        {(mode === "fullscreen" || mode === "area" || (mode === "record" && recordSource === "screen")) && monitors.length > 1 && (
            <div className="monitor-selector">
              <label>Monitor: </label>
              <select
@@ -506,18 +970,99 @@ function App() {
                    {m.name} ({m.width}x{m.height}) {m.is_primary ? "(Primary)" : ""}
                  </option>
                ))}
+               {mode === "fullscreen" && <option value={ALL_MONITORS}>All monitors</option>}
              </select>
              <button onClick={fetchMonitors}>↻</button>
            </div>
         )}
 
+        {mode === "record" && (
+          <div className="record-options">
+            <label>Source: </label>
+            <select
+              value={recordSource}
+              disabled={isRecording}
+              onChange={(e) => setRecordSource(e.target.value as RecordSource)}
+            >
+              <option value="screen">Screen</option>
+              <option value="window">Window</option>
+              <option value="followWindow">Follow window</option>
+            </select>
+            <label>Format: </label>
+            <select
+              value={recordFormat}
+              disabled={isRecording}
+              onChange={(e) => setRecordFormat(e.target.value as RecordingFormat)}
+            >
+              <option value="webm">Video (WebM)</option>
+              <option value="gif">GIF</option>
+            </select>
+            <label>Stop after (min): </label>
+            <input
+              type="number"
+              min="0"
+              className="limit-input"
+              value={maxMinutes}
+              disabled={isRecording}
+              onChange={(e) => setMaxMinutes(e.target.value)}
+            />
+            <label>or (MB): </label>
+            <input
+              type="number"
+              min="0"
+              className="limit-input"
+              value={maxMegabytes}
+              disabled={isRecording}
+              onChange={(e) => setMaxMegabytes(e.target.value)}
+            />
+            {recordFormat === "gif" && (
+              <>
+                <select
+                  value={gifPalette}
+                  disabled={isRecording}
+                  onChange={(e) => setGifPalette(e.target.value as GifPalette)}
+                >
+                  <option value="global">One palette</option>
+                  <option value="perFrame">Palette per frame</option>
+                </select>
+                <select
+                  value={gifDither}
+                  disabled={isRecording}
+                  onChange={(e) => setGifDither(e.target.value as GifDither)}
+                >
+                  <option value="ordered">Ordered dithering</option>
+                  <option value="errorDiffusion">Diffusion dithering</option>
+                  <option value="none">No dithering</option>
+                </select>
+              </>
+            )}
+          </div>
+        )}
+
         <div className="row">
           {mode === "record" ? (
-             !isRecording ? (
-                <button onClick={startRecording} className="record-btn">Start Recording</button>
-             ) : (
-                <button onClick={stopRecording} className="stop-btn">Stop Recording</button>
-             )
+            <>
+              {!isRecording ? (
+                <button
+                  onClick={startRecording}
+                  className="record-btn"
+                  disabled={recordingState === "finalizing"}
+                >
+                  {recordingState === "finalizing" ? "Saving..." : "Start Recording"}
+                </button>
+              ) : (
+                <>
+                  <button onClick={togglePause}>
+                    {recordingState === "paused" ? "Resume" : "Pause"}
+                  </button>
+                  <button onClick={stopRecording} className="stop-btn">Stop Recording</button>
+                </>
+              )}
+              <button onClick={toggleReplay}>
+                {replayActive ? "Stop Replay Buffer" : "Start Replay Buffer"}
+              </button>
+              {replayActive && <button onClick={saveReplay}>Save Replay</button>}
+            </>
           ) : (
             <>
               <button onClick={capture} disabled={loading}>
@@ -535,13 +1080,13 @@ function App() {
 
       <p>{status}</p>
 
-      {/* Hidden canvas for recording stream */}
-      <canvas ref={canvasRef} style={{ display: "none" }} />
+      {/* Recording preview */}
+      <canvas ref={canvasRef} className="recording-preview" style={{ display: isRecording ? "block" : "none" }} />
 
-      {originalImage && mode === "area" && (
+      {areaCapture && mode === "area" && (
         <div className="crop-container">
           <ReactCrop crop={crop} onChange={(c) => setCrop(c)} onComplete={(c) => setCompletedCrop(c)}>
-            <img ref={imgRef} src={`data:image/png;base64,${originalImage}`} alt="Crop source" />
+            <img ref={imgRef} src={captureUrl(areaCapture)} alt="Crop source" />
           </ReactCrop>
           <button onClick={confirmCrop} className="confirm-crop">Confirm Crop</button>
         </div>
@@ -549,7 +1094,7 @@ function App() {
 
       {image && mode !== "record" && (
         <div className="preview">
-           <ImageEditor ref={editorRef} imageData={image} />
+           <ImageEditor key={image.id} ref={editorRef} src={captureUrl(image)} />
         </div>
       )}
     </main>
@@ -2,17 +2,19 @@ import { useState, useRef, useEffect, useImperativeHandle, forwardRef } from "re
 import "./ImageEditor.css";
 
 interface ImageEditorProps {
-  imageData: string; // Base64
+  src: string; // A capture:// URL
 }
 
 export interface ImageEditorRef {
-  getMergedImage: () => Promise<string>;
+  hasAnnotations: () => boolean;
+  getMergedImage: () => Promise<Uint8Array>; // PNG bytes
 }
 
-const ImageEditor = forwardRef<ImageEditorRef, ImageEditorProps>(({ imageData }, ref) => {
+const ImageEditor = forwardRef<ImageEditorRef, ImageEditorProps>(({ src }, ref) => {
   const containerRef = useRef<HTMLDivElement>(null);
   const canvasRef = useRef<HTMLCanvasElement>(null);
   const [isDrawing, setIsDrawing] = useState(false);
+  const annotated = useRef(false);
   const [tool, setTool] = useState<"pen" | "eraser">("pen");
   const [color, setColor] = useState("#ff0000");
   const [opacity, setOpacity] = useState(1);
@@ -27,8 +29,8 @@ const ImageEditor = forwardRef<ImageEditorRef, ImageEditorProps>(({ imageData },
     img.onload = () => {
       setDimensions({ width: img.width, height: img.height });
     };
-    img.src = `data:image/png;base64,${imageData}`;
-  }, [imageData]);
+    img.src = src;
+  }, [src]);
 
   useEffect(() => {
     // Initialize canvas context settings when tool/color/etc changes
@@ -44,9 +46,12 @@ const ImageEditor = forwardRef<ImageEditorRef, ImageEditorProps>(({ imageData },
   }, [tool, color, opacity, lineWidth]);
 
   useImperativeHandle(ref, () => ({
+    hasAnnotations: () => annotated.current,
     getMergedImage: async () => {
-      return new Promise((resolve) => {
+      return new Promise((resolve, reject) => {
         const img = new Image();
+        // The capture protocol allows any origin, which keeps the canvas exportable
+        img.crossOrigin = "anonymous";
         img.onload = () => {
           const exportCanvas = document.createElement("canvas");
           exportCanvas.width = dimensions.width;
@@ -60,10 +65,17 @@ const ImageEditor = forwardRef<ImageEditorRef, ImageEditorProps>(({ imageData },
                  ctx.drawImage(canvasRef.current, 0, 0);
              }
              // 3. Export
-             resolve(exportCanvas.toDataURL("image/png").split(",")[1]);
+             exportCanvas.toBlob(async (blob) => {
+                 if (blob) {
+                     resolve(new Uint8Array(await blob.arrayBuffer()));
+                 } else {
+                     reject(new Error("Could not encode the annotated image"));
+                 }
+             }, "image/png");
           }
         };
-        img.src = `data:image/png;base64,${imageData}`;
+        img.onerror = () => reject(new Error("Could not load the capture"));
+        img.src = src;
       });
     }
   }));
@@ -75,6 +87,7 @@ const ImageEditor = forwardRef<ImageEditorRef, ImageEditorProps>(({ imageData },
     if (!ctx) return;
 
     setIsDrawing(true);
+    annotated.current = true;
     const { x, y } = getCoordinates(e, canvas);
     
     ctx.beginPath();
@@ -200,7 +213,7 @@ const ImageEditor = forwardRef<ImageEditorRef, ImageEditorProps>(({ imageData },
 
       <div className="canvas-wrapper" ref={containerRef}>
         <img 
-            src={`data:image/png;base64,${imageData}`} 
+            src={src} 
             alt="Base" 
             style={{ display: "block", maxWidth: "100%" }} 
         />